@@ -0,0 +1,4 @@
+ALTER TABLE episodes ADD COLUMN enclength BIGINT;
+ALTER TABLE episodes ADD COLUMN pubdate TIMESTAMP WITH TIME ZONE;
+ALTER TABLE episodes ADD COLUMN description TEXT;
+ALTER TABLE episodes ADD COLUMN link TEXT;
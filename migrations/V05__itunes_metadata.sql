@@ -0,0 +1,17 @@
+ALTER TABLE podcasts ADD COLUMN author TEXT;
+ALTER TABLE podcasts ADD COLUMN summary TEXT;
+ALTER TABLE podcasts ADD COLUMN image TEXT;
+ALTER TABLE podcasts ADD COLUMN explicit BOOLEAN;
+ALTER TABLE podcasts ADD COLUMN block BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE podcasts ADD COLUMN new_feed_url TEXT;
+ALTER TABLE podcasts ADD COLUMN full_episodes_only BOOLEAN NOT NULL DEFAULT false;
+
+ALTER TABLE episodes ADD COLUMN duration INTEGER;
+ALTER TABLE episodes ADD COLUMN episode_number INTEGER;
+ALTER TABLE episodes ADD COLUMN season_number INTEGER;
+ALTER TABLE episodes ADD COLUMN episode_type TEXT;
+ALTER TABLE episodes ADD COLUMN explicit BOOLEAN;
+ALTER TABLE episodes ADD COLUMN image TEXT;
+ALTER TABLE episodes ADD COLUMN author TEXT;
+ALTER TABLE episodes ADD COLUMN summary TEXT;
+ALTER TABLE episodes ADD COLUMN block BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,37 @@
+ALTER TABLE podcasts ADD COLUMN podcast_guid TEXT;
+ALTER TABLE podcasts ADD COLUMN locked BOOLEAN;
+
+CREATE TABLE episode_chapters (
+    castid INTEGER NOT NULL,
+    episodeid INTEGER NOT NULL,
+    url TEXT NOT NULL,
+    chaptype TEXT,
+    CONSTRAINT episode_chapters_castid_episodeid_key UNIQUE (castid, episodeid)
+);
+
+CREATE TABLE episode_transcripts (
+    castid INTEGER NOT NULL,
+    episodeid INTEGER NOT NULL,
+    url TEXT NOT NULL,
+    transtype TEXT,
+    language TEXT,
+    rel TEXT,
+    CONSTRAINT episode_transcripts_castid_episodeid_url_key UNIQUE (castid, episodeid, url)
+);
+
+CREATE TABLE podcast_persons (
+    castid INTEGER NOT NULL,
+    episodeid INTEGER,
+    name TEXT NOT NULL,
+    role TEXT,
+    grp TEXT,
+    img TEXT,
+    href TEXT
+);
+
+CREATE TABLE podcast_funding (
+    castid INTEGER NOT NULL,
+    url TEXT NOT NULL,
+    message TEXT,
+    CONSTRAINT podcast_funding_castid_url_key UNIQUE (castid, url)
+);
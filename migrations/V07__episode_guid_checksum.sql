@@ -0,0 +1,6 @@
+ALTER TABLE episodes ADD COLUMN guid TEXT;
+ALTER TABLE episodes ADD COLUMN checksum TEXT;
+UPDATE episodes SET checksum = epguid WHERE epguid ~ '^[0-9a-f]{32}$';
+UPDATE episodes SET guid = epguid WHERE epguid IS NOT NULL AND epguid !~ '^[0-9a-f]{32}$';
+ALTER TABLE episodes DROP COLUMN epguid;
+CREATE INDEX episodes_castid_guid_idx ON episodes (castid, guid);
@@ -0,0 +1,6 @@
+CREATE TABLE podcast_feedurl_history (
+    castid INTEGER NOT NULL,
+    feedurl TEXT NOT NULL,
+    changed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    CONSTRAINT podcast_feedurl_history_castid_feedurl_key UNIQUE (castid, feedurl)
+);
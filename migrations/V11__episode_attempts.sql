@@ -0,0 +1 @@
+ALTER TABLE episodes ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+ALTER TABLE podcasts ADD COLUMN filename_template TEXT;
+ALTER TABLE episodes ADD COLUMN filename TEXT;
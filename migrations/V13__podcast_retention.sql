@@ -0,0 +1,3 @@
+ALTER TABLE podcasts ADD COLUMN keep_latest INTEGER;
+ALTER TABLE podcasts ADD COLUMN max_age_days INTEGER;
+ALTER TABLE podcasts ADD COLUMN max_total_bytes BIGINT;
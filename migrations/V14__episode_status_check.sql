@@ -0,0 +1,6 @@
+ALTER TABLE episodes ADD CONSTRAINT episodes_status_check CHECK (
+    status IN (
+        'Ready', 'Downloading', 'Downloaded', 'Played', 'Error', 'Skipped', 'Deleted',
+        'Archived'
+    )
+);
@@ -0,0 +1 @@
+ALTER TABLE podcasts ADD COLUMN active BOOLEAN NOT NULL DEFAULT true;
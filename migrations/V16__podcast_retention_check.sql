@@ -0,0 +1,6 @@
+UPDATE podcasts SET keep_latest = NULL WHERE keep_latest < 0;
+UPDATE podcasts SET max_age_days = NULL WHERE max_age_days < 0;
+UPDATE podcasts SET max_total_bytes = NULL WHERE max_total_bytes < 0;
+ALTER TABLE podcasts ADD CONSTRAINT podcasts_keep_latest_check CHECK (keep_latest >= 0);
+ALTER TABLE podcasts ADD CONSTRAINT podcasts_max_age_days_check CHECK (max_age_days >= 0);
+ALTER TABLE podcasts ADD CONSTRAINT podcasts_max_total_bytes_check CHECK (max_total_bytes >= 0);
@@ -4,10 +4,34 @@ use std::{ops::Deref, path::Path, sync::Arc};
 
 use stack_string::StackString;
 
+use crate::{
+    episode::DEFAULT_MAX_DOWNLOAD_ATTEMPTS,
+    exponential_retry::{RetryPolicy, MAX_RETRY_DELAY},
+    pod_connection::{DEFAULT_MAX_CONCURRENT_REQUESTS, DEFAULT_MAX_REQUESTS_PER_HOST},
+};
+
 #[derive(Default, Debug, Deserialize)]
 pub struct ConfigInner {
     pub database_url: StackString,
     pub user: StackString,
+    /// Also download `podcast:chapters` and `podcast:transcript` files
+    #[serde(default)]
+    pub download_extras: bool,
+    /// Delay before the first retry of a request in seconds
+    pub retry_base_delay: Option<f64>,
+    /// Upper bound of the delay between retries in seconds
+    pub retry_max_delay: Option<f64>,
+    /// Fraction of the retry delay added at random
+    pub retry_jitter: Option<f64>,
+    /// Number of attempts made for each request
+    pub retry_max_attempts: Option<u32>,
+    /// Number of feed fetches and downloads running at the same time
+    pub max_concurrent_requests: Option<usize>,
+    /// Number of requests running at the same time against a single host
+    pub max_requests_per_host: Option<usize>,
+    /// Number of times a refresh tries to download an episode before
+    /// leaving it in the `Error` state
+    pub max_download_attempts: Option<i32>,
 }
 
 #[derive(Default, Debug, Clone)]
@@ -17,6 +41,43 @@ impl ConfigInner {
     fn from_env() -> Self {
         envy::from_env().unwrap_or_else(|_| Self::default())
     }
+
+    /// Retry policy for http requests, unset, negative or non-finite values
+    /// keep their defaults and delays are capped at `MAX_RETRY_DELAY`
+    #[must_use]
+    pub fn retry_policy(&self) -> RetryPolicy {
+        let default = RetryPolicy::default();
+        let valid = |value: Option<f64>| value.filter(|v| v.is_finite() && *v >= 0.0);
+        RetryPolicy {
+            base_delay: valid(self.retry_base_delay)
+                .map_or(default.base_delay, |v| v.min(MAX_RETRY_DELAY)),
+            max_delay: valid(self.retry_max_delay)
+                .map_or(default.max_delay, |v| v.min(MAX_RETRY_DELAY)),
+            jitter: valid(self.retry_jitter).map_or(default.jitter, |v| v.min(1.0)),
+            max_attempts: self.retry_max_attempts.unwrap_or(default.max_attempts),
+        }
+    }
+
+    #[must_use]
+    pub fn max_concurrent_requests(&self) -> usize {
+        self.max_concurrent_requests
+            .unwrap_or(DEFAULT_MAX_CONCURRENT_REQUESTS)
+            .max(1)
+    }
+
+    #[must_use]
+    pub fn max_requests_per_host(&self) -> usize {
+        self.max_requests_per_host
+            .unwrap_or(DEFAULT_MAX_REQUESTS_PER_HOST)
+            .max(1)
+    }
+
+    #[must_use]
+    pub fn max_download_attempts(&self) -> i32 {
+        self.max_download_attempts
+            .unwrap_or(DEFAULT_MAX_DOWNLOAD_ATTEMPTS)
+            .max(1)
+    }
 }
 
 impl Config {
@@ -52,3 +113,40 @@ impl Deref for Config {
         &self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::ConfigInner,
+        exponential_retry::{RetryPolicy, MAX_RETRY_DELAY},
+    };
+
+    #[test]
+    fn test_retry_policy_bad_values() {
+        let default = RetryPolicy::default();
+        let config = ConfigInner {
+            retry_base_delay: Some(f64::NAN),
+            retry_max_delay: Some(1e300),
+            retry_jitter: Some(-0.5),
+            ..ConfigInner::default()
+        };
+        let policy = config.retry_policy();
+        assert_eq!(policy.base_delay, default.base_delay);
+        assert_eq!(policy.max_delay, MAX_RETRY_DELAY);
+        assert_eq!(policy.jitter, default.jitter);
+        for attempt in 1..100 {
+            assert!(policy.delay(attempt, 0.99).as_secs_f64() <= MAX_RETRY_DELAY);
+        }
+
+        let config = ConfigInner {
+            retry_base_delay: Some(-1.0),
+            retry_max_delay: Some(f64::INFINITY),
+            retry_jitter: Some(2.0),
+            ..ConfigInner::default()
+        };
+        let policy = config.retry_policy();
+        assert_eq!(policy.base_delay, default.base_delay);
+        assert_eq!(policy.max_delay, default.max_delay);
+        assert_eq!(policy.jitter, 1.0);
+    }
+}
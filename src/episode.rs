@@ -5,17 +5,27 @@ use postgres_query::FromSqlRow;
 use reqwest::Url;
 use stack_string::{format_sstr, StackString};
 use std::{
-    borrow::Borrow,
-    hash::{Hash, Hasher},
-    path::Path,
+    convert::TryFrom,
+    path::{Path, PathBuf},
 };
-use tokio::fs::remove_file;
+use time::OffsetDateTime;
+use tokio::fs;
+use tokio_postgres::types::ToSql;
 
 use crate::{
-    episode_status::EpisodeStatus, get_md5sum, pgpool::PgPool, pod_connection::PodConnection,
+    episode_status::EpisodeStatus,
+    filename_template::{basename_filter, reserve_filename, ReservedNames},
+    get_md5sum,
+    listing::Listing,
+    media_type::{detect_extension, same_format},
+    pgpool::PgPool,
+    pod_connection::{is_permanent_failure, PodConnection, SizeMismatch},
 };
 
-#[derive(Default, Clone, Debug, FromSqlRow, Eq)]
+/// Number of times a refresh tries to download an episode by default
+pub const DEFAULT_MAX_DOWNLOAD_ATTEMPTS: i32 = 3;
+
+#[derive(Default, Clone, Debug, FromSqlRow, PartialEq, Eq)]
 pub struct Episode {
     pub castid: i32,
     pub episodeid: i32,
@@ -23,47 +33,205 @@ pub struct Episode {
     pub epurl: StackString,
     pub enctype: StackString,
     pub status: EpisodeStatus,
-    pub epguid: Option<StackString>,
+    pub guid: Option<StackString>,
+    pub checksum: Option<StackString>,
+    pub enclength: Option<i64>,
+    pub pubdate: Option<OffsetDateTime>,
+    pub description: Option<StackString>,
+    pub link: Option<StackString>,
+    pub duration: Option<i32>,
+    pub episode_number: Option<i32>,
+    pub season_number: Option<i32>,
+    pub episode_type: Option<StackString>,
+    pub explicit: Option<bool>,
+    pub image: Option<StackString>,
+    pub author: Option<StackString>,
+    pub summary: Option<StackString>,
+    pub block: bool,
+    pub error_message: Option<StackString>,
+    pub attempts: i32,
+    pub filename: Option<StackString>,
 }
 
-impl PartialEq for Episode {
-    fn eq(&self, other: &Self) -> bool {
-        self.title == other.title
-    }
+/// Which episodes `Episode::get_episodes` returns, every field that is set
+/// narrows the selection
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct EpisodeFilter {
+    pub castid: Option<i32>,
+    /// Any of these states, all states if empty
+    pub status: Vec<EpisodeStatus>,
+    /// Published at or after
+    pub since: Option<OffsetDateTime>,
+    /// Published before
+    pub until: Option<OffsetDateTime>,
+    /// Case insensitive substring of the title
+    pub title: Option<StackString>,
+    /// Case insensitive posix regular expression matching the title
+    pub title_regex: Option<StackString>,
+    /// Column to order by, nulls first, before the default order of
+    /// podcast, season, episode and publishing date
+    pub sort: Option<StackString>,
+    /// Reverse the order
+    pub reverse: bool,
+    /// Return at most this many episodes
+    pub limit: Option<i64>,
+    /// Skip this many episodes of the ordered selection
+    pub offset: Option<i64>,
 }
 
-impl Hash for Episode {
-    fn hash<H>(&self, state: &mut H)
-    where
-        H: Hasher,
-    {
-        self.title.hash(state);
+/// Columns that break ties of the sort column, and whether they sort nulls
+/// last in ascending order
+const DEFAULT_ORDER: [(&str, bool); 5] = [
+    ("castid", true),
+    ("season_number", true),
+    ("episode_number", true),
+    ("pubdate", true),
+    ("episodeid", true),
+];
+
+impl EpisodeFilter {
+    /// The `ORDER BY` clause, `limit` and `offset` apply to the sorted
+    /// episodes so sorting happens in the database
+    fn order_by(&self) -> Result<String, Error> {
+        let mut order = Vec::new();
+        if let Some(sort) = self.sort.as_ref() {
+            let Some(column) = Episode::COLUMNS.iter().find(|c| **c == sort.as_str()) else {
+                return Err(format_err!(
+                    "Unknown column {sort}, expected one of {}",
+                    Episode::COLUMNS.join(", ")
+                ));
+            };
+            order.push((*column, false));
+        }
+        order.extend(DEFAULT_ORDER);
+        let terms: Vec<_> = order
+            .into_iter()
+            .map(|(column, nulls_last)| {
+                let direction = if self.reverse { "DESC" } else { "ASC" };
+                let nulls = if nulls_last == self.reverse {
+                    "FIRST"
+                } else {
+                    "LAST"
+                };
+                format!("{column} {direction} NULLS {nulls}")
+            })
+            .collect();
+        Ok(format!(" ORDER BY {}", terms.join(", ")))
+    }
+
+    /// The select statement and its parameters
+    fn to_sql(&self) -> Result<(String, Vec<Box<dyn ToSql + Sync + Send>>), Error> {
+        let mut conditions: Vec<String> = Vec::new();
+        let mut params: Vec<Box<dyn ToSql + Sync + Send>> = Vec::new();
+        if let Some(castid) = self.castid {
+            params.push(Box::new(castid));
+            conditions.push(format!("castid = ${}", params.len()));
+        }
+        if !self.status.is_empty() {
+            params.push(Box::new(self.status.clone()));
+            conditions.push(format!("status = ANY(${})", params.len()));
+        }
+        if let Some(since) = self.since {
+            params.push(Box::new(since));
+            conditions.push(format!("pubdate >= ${}", params.len()));
+        }
+        if let Some(until) = self.until {
+            params.push(Box::new(until));
+            conditions.push(format!("pubdate < ${}", params.len()));
+        }
+        if let Some(title) = self.title.as_ref() {
+            params.push(Box::new(format!("%{}%", escape_like(title))));
+            conditions.push(format!("title ILIKE ${}", params.len()));
+        }
+        if let Some(title_regex) = self.title_regex.as_ref() {
+            params.push(Box::new(title_regex.clone()));
+            conditions.push(format!("title ~* ${}", params.len()));
+        }
+        let mut query = String::from(
+            "SELECT castid, episodeid, title, epurl, enctype, status, guid, checksum, enclength, \
+             pubdate, description, link, duration, episode_number, season_number, episode_type, \
+             explicit, image, author, summary, block, error_message, attempts, filename FROM \
+             episodes",
+        );
+        if !conditions.is_empty() {
+            query.push_str(" WHERE ");
+            query.push_str(&conditions.join(" AND "));
+        }
+        query.push_str(&self.order_by()?);
+        if let Some(limit) = self.limit {
+            params.push(Box::new(limit));
+            query.push_str(&format!(" LIMIT ${}", params.len()));
+        }
+        if let Some(offset) = self.offset {
+            params.push(Box::new(offset));
+            query.push_str(&format!(" OFFSET ${}", params.len()));
+        }
+        Ok((query, params))
     }
 }
 
-impl Borrow<str> for Episode {
-    fn borrow(&self) -> &str {
-        self.title.as_str()
+/// Escape the wildcards of a `LIKE` pattern
+fn escape_like(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
     }
+    escaped
 }
 
-fn basename_filter(title: &str) -> String {
-    title
-        .to_lowercase()
-        .chars()
-        .filter_map(|c| match c {
-            'a'..='z' | '0'..='9' => Some(c),
-            ' ' => Some('_'),
-            _ => None,
-        })
-        .collect()
+/// Rename a downloaded file whose extension doesn't match the format of its
+/// contents to a name not in `reserved`, returns the path of the file
+async fn correct_extension(
+    directory: &Path,
+    outfile: &Path,
+    content_type: Option<&str>,
+    reserved: &ReservedNames,
+) -> Result<PathBuf, Error> {
+    let Some(ext) = detect_extension(outfile, content_type)? else {
+        return Ok(outfile.to_path_buf());
+    };
+    if let Some(current) = outfile.extension() {
+        if same_format(&current.to_string_lossy(), ext) {
+            return Ok(outfile.to_path_buf());
+        }
+    }
+    let filename = outfile.with_extension(ext);
+    let filename = filename
+        .file_name()
+        .ok_or_else(|| format_err!("No file name {}", outfile.display()))?
+        .to_string_lossy();
+    let filename = reserve_filename(&filename, directory, reserved);
+    let newfile = directory.join(filename.as_str());
+    debug!("rename {} {}", outfile.display(), newfile.display());
+    fs::rename(outfile, &newfile).await?;
+    Ok(newfile)
 }
 
 #[allow(clippy::similar_names)]
 impl Episode {
+    /// Last segment of the enclosure url path
     /// # Errors
     /// Return error if parsing `epurl` fails
     pub fn url_basename(&self) -> Result<StackString, Error> {
+        let epurl: Url = self.epurl.parse()?;
+        epurl
+            .path()
+            .split('/')
+            .next_back()
+            .map(Into::into)
+            .ok_or_else(|| format_err!("No basename"))
+    }
+
+    /// Name of the file of an episode of a podcast without a filename
+    /// template: the last segment of the url, or the title for hosts that use
+    /// the same file name for every episode.  Episodes downloaded before file
+    /// names were stored are still on disk under these names.
+    /// # Errors
+    /// Return error if parsing `epurl` fails
+    pub fn default_basename(&self) -> Result<StackString, Error> {
         if self.epurl.ends_with("media.mp3")
             || self.epurl.contains("https://feeds.acast.com")
             || self.epurl.contains("cloudfront.net")
@@ -80,13 +248,60 @@ impl Episode {
                 .into();
             Ok(basename)
         } else {
-            let epurl: Url = self.epurl.parse()?;
-            epurl
-                .path()
-                .split('/')
-                .next_back()
-                .map(Into::into)
-                .ok_or_else(|| format_err!("No basename"))
+            self.url_basename()
+        }
+    }
+
+    /// Copy the itunes metadata of the same episode parsed from the feed,
+    /// returns true if anything changed
+    pub fn update_metadata(&mut self, item: &Self) -> bool {
+        let changed = self.duration != item.duration
+            || self.episode_number != item.episode_number
+            || self.season_number != item.season_number
+            || self.episode_type != item.episode_type
+            || self.explicit != item.explicit
+            || self.image != item.image
+            || self.author != item.author
+            || self.summary != item.summary
+            || self.block != item.block;
+        if changed {
+            self.duration = item.duration;
+            self.episode_number = item.episode_number;
+            self.season_number = item.season_number;
+            self.episode_type.clone_from(&item.episode_type);
+            self.explicit = item.explicit;
+            self.image.clone_from(&item.image);
+            self.author.clone_from(&item.author);
+            self.summary.clone_from(&item.summary);
+            self.block = item.block;
+        }
+        changed
+    }
+
+    /// Move the episode to `status`, every status change goes through here
+    /// so that episodes only follow the allowed lifecycle
+    /// # Errors
+    /// Return error if the transition isn't allowed
+    pub fn set_status(&mut self, status: EpisodeStatus) -> Result<(), Error> {
+        if !self.status.can_transition_to(status) {
+            return Err(format_err!(
+                "Episode {} can't go from {} to {status}",
+                self.episodeid,
+                self.status
+            ));
+        }
+        self.status = status;
+        Ok(())
+    }
+
+    /// Name of the downloaded file in the podcast directory, episodes from
+    /// before file names were stored use the name derived from the url
+    /// # Errors
+    /// Return error if parsing `epurl` fails
+    pub fn file_name(&self) -> Result<StackString, Error> {
+        match self.filename.as_ref() {
+            Some(filename) => Ok(filename.clone()),
+            None => self.default_basename(),
         }
     }
 
@@ -95,7 +310,10 @@ impl Episode {
     pub async fn from_index(pool: &PgPool, cid: i32, eid: i32) -> Result<Option<Self>, Error> {
         let query = r"
             SELECT
-                castid, episodeid, title, epurl, enctype, status, epguid
+                castid, episodeid, title, epurl, enctype, status, guid, checksum,
+                enclength, pubdate, description, link, duration, episode_number,
+                season_number, episode_type, explicit, image, author, summary, block,
+                error_message, attempts, filename
             FROM episodes
             WHERE castid = $1 AND episodeid = $2
         ";
@@ -111,7 +329,10 @@ impl Episode {
     pub async fn from_epurl(pool: &PgPool, cid: i32, epurl: &str) -> Result<Option<Self>, Error> {
         let query = r"
             SELECT
-                castid, episodeid, title, epurl, enctype, status, epguid
+                castid, episodeid, title, epurl, enctype, status, guid, checksum,
+                enclength, pubdate, description, link, duration, episode_number,
+                season_number, episode_type, explicit, image, author, summary, block,
+                error_message, attempts, filename
             FROM episodes
             WHERE castid = $1 AND epurl = $2
         ";
@@ -130,17 +351,20 @@ impl Episode {
 
     /// # Errors
     /// Return error if db query fails
-    pub async fn from_epguid(pool: &PgPool, cid: i32, epguid: &str) -> Result<Option<Self>, Error> {
+    pub async fn from_guid(pool: &PgPool, cid: i32, guid: &str) -> Result<Option<Self>, Error> {
         let query = r"
             SELECT
-                castid, episodeid, title, epurl, enctype, status, epguid
+                castid, episodeid, title, epurl, enctype, status, guid, checksum,
+                enclength, pubdate, description, link, duration, episode_number,
+                season_number, episode_type, explicit, image, author, summary, block,
+                error_message, attempts, filename
             FROM episodes
-            WHERE castid = $1 AND epguid = $2
+            WHERE castid = $1 AND guid = $2
         ";
         if let Some(row) = pool
             .get()
             .await?
-            .query(query, &[&cid, &epguid])
+            .query(query, &[&cid, &guid])
             .await?
             .first()
         {
@@ -153,15 +377,23 @@ impl Episode {
     /// # Errors
     /// Return error if db query fails
     pub async fn get_all_episodes(pool: &PgPool, cid: i32) -> Result<Vec<Self>, Error> {
-        let query = r"
-            SELECT
-                castid, episodeid, title, epurl, enctype, status, epguid
-            FROM episodes
-            WHERE castid = $1
-        ";
+        let filter = EpisodeFilter {
+            castid: Some(cid),
+            ..EpisodeFilter::default()
+        };
+        Self::get_episodes(pool, &filter).await
+    }
+
+    /// Episodes matching `filter`, ordered by its sort column and then by
+    /// podcast and as they were published
+    /// # Errors
+    /// Return error if the sort column doesn't exist or db query fails
+    pub async fn get_episodes(pool: &PgPool, filter: &EpisodeFilter) -> Result<Vec<Self>, Error> {
+        let (query, params) = filter.to_sql()?;
+        let params: Vec<_> = params.iter().map(|p| &**p as &(dyn ToSql + Sync)).collect();
         pool.get()
             .await?
-            .query(query, &[&cid])
+            .query(&query, &params)
             .await?
             .iter()
             .map(|row| Ok(Self::from_row(row)?))
@@ -175,9 +407,15 @@ impl Episode {
         let query = postgres_query::query!(
             r#"
             INSERT INTO episodes (
-                castid, episodeid, title, epurl, enctype, status, epguid
+                castid, episodeid, title, epurl, enctype, status, guid, checksum,
+                enclength, pubdate, description, link, duration, episode_number,
+                season_number, episode_type, explicit, image, author, summary, block,
+                error_message, attempts, filename
             ) VALUES (
-                $castid, $episodeid, $title, $epurl, $enctype, $status, $epguid
+                $castid, $episodeid, $title, $epurl, $enctype, $status, $guid, $checksum,
+                $enclength, $pubdate, $description, $link, $duration, $episode_number,
+                $season_number, $episode_type, $explicit, $image, $author, $summary, $block,
+                $error_message, $attempts, $filename
             )
         "#,
             castid = self.castid,
@@ -186,7 +424,24 @@ impl Episode {
             epurl = self.epurl,
             enctype = self.enctype,
             status = status,
-            epguid = self.epguid
+            guid = self.guid,
+            checksum = self.checksum,
+            enclength = self.enclength,
+            pubdate = self.pubdate,
+            description = self.description,
+            link = self.link,
+            duration = self.duration,
+            episode_number = self.episode_number,
+            season_number = self.season_number,
+            episode_type = self.episode_type,
+            explicit = self.explicit,
+            image = self.image,
+            author = self.author,
+            summary = self.summary,
+            block = self.block,
+            error_message = self.error_message,
+            attempts = self.attempts,
+            filename = self.filename
         );
         pool.get()
             .await?
@@ -202,7 +457,13 @@ impl Episode {
         let query = postgres_query::query!(
             r#"
                 UPDATE episodes
-                SET title=$title,epurl=$epurl,enctype=$enctype,status=$status,epguid=$epguid
+                SET title=$title,epurl=$epurl,enctype=$enctype,status=$status,guid=$guid,
+                    checksum=$checksum,
+                    enclength=$enclength,pubdate=$pubdate,description=$description,link=$link,
+                    duration=$duration,episode_number=$episode_number,
+                    season_number=$season_number,episode_type=$episode_type,
+                    explicit=$explicit,image=$image,author=$author,summary=$summary,block=$block,
+                    error_message=$error_message,attempts=$attempts,filename=$filename
                 WHERE castid=$castid AND episodeid=$episodeid
             "#,
             castid = self.castid,
@@ -211,7 +472,24 @@ impl Episode {
             epurl = self.epurl,
             enctype = self.enctype,
             status = status,
-            epguid = self.epguid
+            guid = self.guid,
+            checksum = self.checksum,
+            enclength = self.enclength,
+            pubdate = self.pubdate,
+            description = self.description,
+            link = self.link,
+            duration = self.duration,
+            episode_number = self.episode_number,
+            season_number = self.season_number,
+            episode_type = self.episode_type,
+            explicit = self.explicit,
+            image = self.image,
+            author = self.author,
+            summary = self.summary,
+            block = self.block,
+            error_message = self.error_message,
+            attempts = self.attempts,
+            filename = self.filename
         );
         pool.get()
             .await?
@@ -220,6 +498,45 @@ impl Episode {
             .map_err(Into::into)
     }
 
+    /// Put episodes that failed to download back into the `Ready` state so
+    /// the next refresh retries them with a fresh count of attempts, returns
+    /// the number of episodes
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn requeue_errors(pool: &PgPool, cid: Option<i32>) -> Result<u64, Error> {
+        let next = EpisodeStatus::Ready;
+        let from = EpisodeStatus::transition_sources(&[EpisodeStatus::Error], next)?;
+        let query = r"
+            UPDATE episodes
+            SET status = $3, error_message = NULL, attempts = 0
+            WHERE status = ANY($2) AND ($1::INTEGER IS NULL OR castid = $1)
+        ";
+        pool.get()
+            .await?
+            .execute(query, &[&cid, &from, &next])
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Episodes still marked `Downloading` were interrupted by a crash, put
+    /// them back into the `Ready` state so their download is resumed
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn recover_interrupted(pool: &PgPool, cid: Option<i32>) -> Result<u64, Error> {
+        let next = EpisodeStatus::Ready;
+        let from = EpisodeStatus::transition_sources(&[EpisodeStatus::Downloading], next)?;
+        let query = r"
+            UPDATE episodes
+            SET status = $3
+            WHERE status = ANY($2) AND ($1::INTEGER IS NULL OR castid = $1)
+        ";
+        pool.get()
+            .await?
+            .execute(query, &[&cid, &from, &next])
+            .await
+            .map_err(Into::into)
+    }
+
     /// # Errors
     /// Return error if db query fails
     pub async fn get_max_epid(pool: &PgPool) -> Result<i32, Error> {
@@ -230,15 +547,27 @@ impl Episode {
             .await?
             .first()
             .ok_or_else(|| format_err!("No episodes"))
-            .and_then(|row| row.try_get(0).map_err(Into::into))
+            .and_then(|row| {
+                let max_epid: Option<i32> = row.try_get(0)?;
+                Ok(max_epid.unwrap_or(0))
+            })
     }
 
+    /// Download the episode into `directory`.  A failed download, including
+    /// one whose size doesn't match the response `Content-Length` or the
+    /// enclosure length, is returned with status `Error` and the reason in
+    /// `error_message`, no file is left at its final path.  A download the
+    /// server answers with 404 or 410 uses up all `max_attempts`, so it isn't
+    /// retried until it is re-queued.  A file whose extension doesn't match
+    /// its format is renamed to a name not in `reserved`.
     /// # Errors
-    /// Return error if db query fails
+    /// Return error if the directory doesn't exist or the file can't be read
     pub async fn download_episode(
         &self,
         conn: &PodConnection,
         directory: &Path,
+        reserved: &ReservedNames,
+        max_attempts: i32,
     ) -> Result<Self, Error> {
         if !directory.exists() {
             Err(format_err!(
@@ -246,33 +575,255 @@ impl Episode {
                 directory.to_string_lossy()
             ))
         } else if let Ok(url) = self.epurl.parse() {
-            let outfile = directory.join(self.url_basename()?.as_str());
-            if outfile.exists() {
-                remove_file(&outfile).await?;
-            }
-            conn.dump_to_file(&url, &outfile).await?;
-            let path = Path::new(&outfile);
-            if path.exists() {
-                let md5sum = get_md5sum(path)?;
-                let mut p = self.clone();
-                debug!("{} {md5sum}", outfile.display());
-                p.epguid.replace(md5sum);
-                p.status = EpisodeStatus::Downloaded;
-                Ok(p)
-            } else {
-                Err(format_err!("Download failed"))
+            // an existing file is only replaced once the new download is complete
+            let outfile = directory.join(self.file_name()?.as_str());
+            let enclength = self
+                .enclength
+                .and_then(|l| u64::try_from(l).ok())
+                .filter(|l| *l > 0);
+            let mut p = self.clone();
+            p.attempts += 1;
+            match conn.dump_to_file(&url, &outfile, enclength).await {
+                Ok(download) => {
+                    let outfile = correct_extension(
+                        directory,
+                        &outfile,
+                        download.content_type.as_ref().map(StackString::as_str),
+                        reserved,
+                    )
+                    .await?;
+                    p.finish_download(&outfile)?;
+                }
+                Err(e) => {
+                    let error_message = match e.downcast_ref::<SizeMismatch>() {
+                        Some(mismatch) => format_sstr!("{mismatch}"),
+                        None => format_sstr!("{e}"),
+                    };
+                    debug!("{} {error_message}", outfile.display());
+                    if is_permanent_failure(&e) {
+                        p.attempts = p.attempts.max(max_attempts);
+                    }
+                    p.checksum = None;
+                    p.set_status(EpisodeStatus::Error)?;
+                    p.error_message = Some(error_message);
+                }
             }
+            Ok(p)
         } else {
             Err(format_err!("Unkown failure {self:?}"))
         }
     }
+
+    /// Record the completed download at `outfile`
+    fn finish_download(&mut self, outfile: &Path) -> Result<(), Error> {
+        self.filename = outfile.file_name().map(|f| f.to_string_lossy().into());
+        let md5sum = get_md5sum(outfile)?;
+        debug!("{} {md5sum}", outfile.display());
+        self.checksum.replace(md5sum);
+        self.set_status(EpisodeStatus::Downloaded)?;
+        self.error_message = None;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use anyhow::Error;
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+    };
+    use time::macros::datetime;
 
-    use crate::{config::Config, episode::Episode, pgpool::PgPool};
+    use crate::{
+        config::Config,
+        episode::{escape_like, Episode, EpisodeFilter, DEFAULT_MAX_DOWNLOAD_ATTEMPTS},
+        episode_status::EpisodeStatus,
+        filename_template::ReservedNames,
+        pgpool::PgPool,
+        pod_connection::{partial_path, validator_path, PodConnection},
+    };
+
+    #[test]
+    fn test_episode_filter_to_sql() -> Result<(), Error> {
+        let (query, params) = EpisodeFilter::default().to_sql()?;
+        assert!(!query.contains("WHERE"));
+        assert!(query.ends_with(
+            "ORDER BY castid ASC NULLS LAST, season_number ASC NULLS LAST, episode_number ASC \
+             NULLS LAST, pubdate ASC NULLS LAST, episodeid ASC NULLS LAST"
+        ));
+        assert!(params.is_empty());
+
+        let filter = EpisodeFilter {
+            castid: Some(19),
+            status: vec![EpisodeStatus::Ready, EpisodeStatus::Error],
+            since: Some(datetime!(2024-01-01 0:00 UTC)),
+            title: Some("50%_off".into()),
+            limit: Some(10),
+            offset: Some(20),
+            ..EpisodeFilter::default()
+        };
+        let (query, params) = filter.to_sql()?;
+        assert!(query.contains(
+            "WHERE castid = $1 AND status = ANY($2) AND pubdate >= $3 AND title ILIKE $4 ORDER BY"
+        ));
+        assert!(query.ends_with("LIMIT $5 OFFSET $6"));
+        assert_eq!(params.len(), 6);
+        assert_eq!(escape_like("50%_off\\"), "50\\%\\_off\\\\");
+
+        // the newest episodes come first before the limit is applied
+        let filter = EpisodeFilter {
+            sort: Some("pubdate".into()),
+            reverse: true,
+            limit: Some(5),
+            ..EpisodeFilter::default()
+        };
+        let (query, _) = filter.to_sql()?;
+        assert!(query.contains(
+            "ORDER BY pubdate DESC NULLS LAST, castid DESC NULLS FIRST, season_number DESC NULLS \
+             FIRST"
+        ));
+        assert!(query.ends_with("episodeid DESC NULLS FIRST LIMIT $1"));
+        let filter = EpisodeFilter {
+            sort: Some("pubdate; DROP TABLE episodes".into()),
+            ..EpisodeFilter::default()
+        };
+        assert!(filter.to_sql().is_err());
+        Ok(())
+    }
+
+    /// Answer one request with each of `responses`, a status line and a
+    /// body, on a port of the loopback interface
+    fn serve_responses(responses: Vec<(&'static str, &'static [u8])>) -> Result<String, Error> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        std::thread::spawn(move || {
+            for (status, body) in responses {
+                let Ok((mut stream, _)) = listener.accept() else {
+                    return;
+                };
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let header = format!(
+                    "HTTP/1.1 {status}\r\nContent-Type: audio/mpeg\r\nContent-Length: {}\r\n\
+                     Connection: close\r\n\r\n",
+                    body.len()
+                );
+                let _ = stream
+                    .write_all(header.as_bytes())
+                    .and_then(|()| stream.write_all(body));
+            }
+        });
+        Ok(format!("http://{addr}/episode.mp3"))
+    }
+
+    #[tokio::test]
+    async fn test_episode_download_enclosure_mismatch() -> Result<(), Error> {
+        let directory = std::env::temp_dir().join("podcatch_test_enclosure_mismatch");
+        if directory.exists() {
+            std::fs::remove_dir_all(&directory)?;
+        }
+        std::fs::create_dir_all(&directory)?;
+        let epurl = serve_responses(vec![
+            ("200 OK", &b"short"[..]),
+            ("200 OK", &b"full episode"[..]),
+        ])?;
+        let epi = Episode {
+            epurl: epurl.into(),
+            enclength: Some(12),
+            filename: Some("episode.mp3".into()),
+            status: EpisodeStatus::Downloading,
+            ..Episode::default()
+        };
+        let conn = PodConnection::new();
+        let reserved = ReservedNames::default();
+        let outfile = directory.join("episode.mp3");
+
+        let p = epi
+            .download_episode(&conn, &directory, &reserved, DEFAULT_MAX_DOWNLOAD_ATTEMPTS)
+            .await?;
+        assert_eq!(p.status, EpisodeStatus::Error);
+        assert_eq!(p.attempts, 1);
+        assert_eq!(p.checksum, None);
+        assert_eq!(
+            p.error_message.as_deref(),
+            Some("download doesn't match enclosure length, got 5 of 12 bytes")
+        );
+        // nothing at the final path that a later run could take as complete
+        assert!(!outfile.exists());
+        assert!(!partial_path(&outfile).exists());
+        assert!(!validator_path(&outfile).exists());
+
+        // re-queued, the episode is downloaded again from scratch
+        let mut p = Episode {
+            error_message: None,
+            attempts: 0,
+            ..p
+        };
+        p.set_status(EpisodeStatus::Ready)?;
+        p.set_status(EpisodeStatus::Downloading)?;
+        let p = p
+            .download_episode(&conn, &directory, &reserved, DEFAULT_MAX_DOWNLOAD_ATTEMPTS)
+            .await?;
+        assert_eq!(p.status, EpisodeStatus::Downloaded);
+        assert!(p.checksum.is_some());
+        assert_eq!(p.filename.as_deref(), Some("episode.mp3"));
+        assert_eq!(std::fs::read(&outfile)?, b"full episode");
+        assert!(!partial_path(&outfile).exists());
+        std::fs::remove_dir_all(&directory)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_episode_download_not_found() -> Result<(), Error> {
+        let directory = std::env::temp_dir().join("podcatch_test_download_not_found");
+        std::fs::create_dir_all(&directory)?;
+        let epurl = serve_responses(vec![("404 Not Found", &b""[..])])?;
+        let epi = Episode {
+            epurl: epurl.into(),
+            filename: Some("episode.mp3".into()),
+            status: EpisodeStatus::Downloading,
+            ..Episode::default()
+        };
+        let conn = PodConnection::new();
+        let reserved = ReservedNames::default();
+        // a missing enclosure isn't retried by the next refresh
+        let p = epi
+            .download_episode(&conn, &directory, &reserved, DEFAULT_MAX_DOWNLOAD_ATTEMPTS)
+            .await?;
+        assert_eq!(p.status, EpisodeStatus::Error);
+        assert_eq!(p.attempts, DEFAULT_MAX_DOWNLOAD_ATTEMPTS);
+        std::fs::remove_dir_all(&directory)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_episode_file_name() -> Result<(), Error> {
+        let epi = Episode {
+            title: "The Big One!".into(),
+            epurl: "https://feeds.acast.com/public/streams/show/episodes/media.mp3".into(),
+            ..Episode::default()
+        };
+        assert_eq!(&epi.url_basename()?, "media.mp3");
+        assert_eq!(&epi.file_name()?, "the_big_one.mp3");
+        let epi = Episode {
+            epurl: "https://cdn.example.com/newrustacean/bonus/11.mp3".into(),
+            ..epi
+        };
+        assert_eq!(&epi.file_name()?, "bonus_11.mp3");
+        let epi = Episode {
+            filename: Some("stored.mp3".into()),
+            ..epi
+        };
+        assert_eq!(&epi.file_name()?, "stored.mp3");
+        Ok(())
+    }
 
     #[tokio::test]
     #[ignore]
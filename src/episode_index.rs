@@ -0,0 +1,252 @@
+use stack_string::StackString;
+use std::{collections::HashMap, fmt, iter::FromIterator};
+
+use crate::episode::Episode;
+
+/// Why a feed item was considered to be an already known episode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EpisodeMatch {
+    Guid,
+    Url,
+    Title,
+}
+
+impl EpisodeMatch {
+    #[must_use]
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Guid => "guid",
+            Self::Url => "url",
+            Self::Title => "title",
+        }
+    }
+}
+
+impl fmt::Display for EpisodeMatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.to_str())
+    }
+}
+
+/// Lowercase alphanumeric words separated by single spaces, so that changes
+/// in case, punctuation or whitespace don't make a title look new
+#[must_use]
+pub fn normalize_title(title: &str) -> StackString {
+    let mut normalized = String::with_capacity(title.len());
+    for word in title
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+    {
+        if !normalized.is_empty() {
+            normalized.push(' ');
+        }
+        normalized.extend(word.chars().flat_map(char::to_lowercase));
+    }
+    normalized.into()
+}
+
+/// Lookup of the known episodes of a podcast by feed guid, enclosure url and
+/// normalized title
+#[derive(Default, Debug)]
+pub struct EpisodeIndex {
+    episodes: Vec<Episode>,
+    by_guid: HashMap<StackString, usize>,
+    by_url: HashMap<StackString, usize>,
+    by_title: HashMap<StackString, Vec<usize>>,
+}
+
+impl EpisodeIndex {
+    #[must_use]
+    pub fn new(episodes: Vec<Episode>) -> Self {
+        let mut by_guid = HashMap::new();
+        let mut by_url = HashMap::new();
+        let mut by_title: HashMap<StackString, Vec<usize>> = HashMap::new();
+        for (idx, epi) in episodes.iter().enumerate() {
+            if let Some(guid) = epi.guid.as_ref() {
+                by_guid.insert(guid.clone(), idx);
+            }
+            by_url.insert(epi.epurl.clone(), idx);
+            by_title
+                .entry(normalize_title(&epi.title))
+                .or_default()
+                .push(idx);
+        }
+        Self {
+            episodes,
+            by_guid,
+            by_url,
+            by_title,
+        }
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.episodes.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.episodes.is_empty()
+    }
+
+    #[must_use]
+    pub fn episodes(&self) -> &[Episode] {
+        &self.episodes
+    }
+
+    /// Find the known episode matching a feed item, trying the feed guid,
+    /// then the enclosure url, then the normalized title.  A title only
+    /// matches when it is unambiguous, and for an item with a guid only an
+    /// episode without one, recorded before guids were stored.  So reused
+    /// titles like "Bonus" are treated as new episodes.
+    #[must_use]
+    pub fn find(
+        &self,
+        guid: Option<&str>,
+        epurl: &str,
+        title: &str,
+    ) -> Option<(&Episode, EpisodeMatch)> {
+        if let Some(idx) = guid.and_then(|g| self.by_guid.get(g)) {
+            return Some((&self.episodes[*idx], EpisodeMatch::Guid));
+        }
+        if let Some(idx) = self.by_url.get(epurl) {
+            return Some((&self.episodes[*idx], EpisodeMatch::Url));
+        }
+        let mut candidates = self
+            .by_title
+            .get(&normalize_title(title))?
+            .iter()
+            .map(|idx| &self.episodes[*idx]);
+        match (candidates.next(), candidates.next()) {
+            // an item with a guid that isn't known only matches an episode
+            // that has no guid to compare
+            (Some(epi), None) if guid.is_none() || epi.guid.is_none() => {
+                Some((epi, EpisodeMatch::Title))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl FromIterator<Episode> for EpisodeIndex {
+    fn from_iter<T: IntoIterator<Item = Episode>>(iter: T) -> Self {
+        Self::new(iter.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        episode::Episode,
+        episode_index::{normalize_title, EpisodeIndex, EpisodeMatch},
+    };
+
+    fn episode(episodeid: i32, title: &str, epurl: &str, guid: Option<&str>) -> Episode {
+        Episode {
+            castid: 1,
+            episodeid,
+            title: title.into(),
+            epurl: epurl.into(),
+            guid: guid.map(Into::into),
+            ..Episode::default()
+        }
+    }
+
+    fn index() -> EpisodeIndex {
+        vec![
+            episode(1, "Episode 1: Pilot", "https://a.example/1.mp3", Some("g1")),
+            episode(2, "Bonus", "https://a.example/b1.mp3", Some("bonus-1")),
+            episode(3, "Legacy Show", "https://a.example/legacy.mp3", None),
+            episode(4, "Repeat", "https://a.example/r1.mp3", None),
+            episode(5, "Repeat", "https://a.example/r2.mp3", None),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn test_normalize_title() {
+        assert_eq!(
+            &normalize_title("  Episode 1:  The PILOT!"),
+            "episode 1 the pilot"
+        );
+        assert_eq!(&normalize_title("Café — Ünïcode"), "café ünïcode");
+        assert_eq!(&normalize_title("???"), "");
+    }
+
+    #[test]
+    fn test_renamed_episode_matches_guid() {
+        let index = index();
+        let (epi, reason) = index
+            .find(Some("g1"), "https://a.example/1.mp3", "Pilot (remastered)")
+            .unwrap();
+        assert_eq!(epi.episodeid, 1);
+        assert_eq!(reason, EpisodeMatch::Guid);
+    }
+
+    #[test]
+    fn test_rehosted_episode_matches_guid() {
+        let index = index();
+        let (epi, reason) = index
+            .find(
+                Some("g1"),
+                "https://cdn.example/pilot.mp3",
+                "Episode 1: Pilot",
+            )
+            .unwrap();
+        assert_eq!(epi.episodeid, 1);
+        assert_eq!(reason, EpisodeMatch::Guid);
+    }
+
+    #[test]
+    fn test_legacy_episode_matches_url_then_title() {
+        let index = index();
+        let (epi, reason) = index
+            .find(Some("new-guid"), "https://a.example/legacy.mp3", "Renamed")
+            .unwrap();
+        assert_eq!(epi.episodeid, 3);
+        assert_eq!(reason, EpisodeMatch::Url);
+
+        let (epi, reason) = index
+            .find(None, "https://cdn.example/legacy.mp3", "legacy show")
+            .unwrap();
+        assert_eq!(epi.episodeid, 3);
+        assert_eq!(reason, EpisodeMatch::Title);
+    }
+
+    #[test]
+    fn test_legacy_episode_matches_title_with_guid() {
+        let index = index();
+        // rows recorded before guids were stored match an item with a guid
+        // and a new url by title
+        let (epi, reason) = index
+            .find(
+                Some("legacy-guid"),
+                "https://cdn.example/track/legacy.mp3",
+                "Legacy Show!",
+            )
+            .unwrap();
+        assert_eq!(epi.episodeid, 3);
+        assert_eq!(reason, EpisodeMatch::Title);
+        // as long as the title is unambiguous
+        assert!(index
+            .find(Some("repeat-guid"), "https://a.example/r3.mp3", "Repeat")
+            .is_none());
+    }
+
+    #[test]
+    fn test_reused_title_is_new_episode() {
+        let index = index();
+        assert!(index
+            .find(Some("bonus-2"), "https://a.example/b2.mp3", "Bonus")
+            .is_none());
+        assert!(index
+            .find(None, "https://a.example/r3.mp3", "Repeat")
+            .is_none());
+        let (epi, reason) = index
+            .find(None, "https://a.example/r2.mp3", "Repeat")
+            .unwrap();
+        assert_eq!(epi.episodeid, 5);
+        assert_eq!(reason, EpisodeMatch::Url);
+    }
+}
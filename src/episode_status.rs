@@ -3,24 +3,92 @@ use bytes::BytesMut;
 use std::{fmt, str::FromStr};
 use tokio_postgres::types::{FromSql, IsNull, ToSql, Type};
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
 pub enum EpisodeStatus {
+    #[default]
     Ready,
+    /// A download was started, left behind only if the process died
+    Downloading,
     Downloaded,
+    Played,
     Error,
     Skipped,
+    /// The file was removed, by the retention policy of the podcast or by
+    /// hand
+    Deleted,
+    /// The file was moved out of the podcast directory
+    Archived,
 }
 
 impl EpisodeStatus {
+    pub const ALL: [Self; 8] = [
+        Self::Ready,
+        Self::Downloading,
+        Self::Downloaded,
+        Self::Played,
+        Self::Error,
+        Self::Skipped,
+        Self::Deleted,
+        Self::Archived,
+    ];
+
     #[must_use]
     pub fn to_str(self) -> &'static str {
         match self {
             Self::Ready => "Ready",
+            Self::Downloading => "Downloading",
             Self::Downloaded => "Downloaded",
+            Self::Played => "Played",
             Self::Error => "Error",
             Self::Skipped => "Skipped",
+            Self::Deleted => "Deleted",
+            Self::Archived => "Archived",
+        }
+    }
+
+    /// Episodes in these states are never downloaded by a refresh
+    #[must_use]
+    pub fn skips_download(self) -> bool {
+        matches!(
+            self,
+            Self::Played | Self::Skipped | Self::Error | Self::Deleted | Self::Archived
+        )
+    }
+
+    /// Whether an episode may move from `self` to `next`, staying in the
+    /// same state is always allowed
+    #[must_use]
+    pub fn can_transition_to(self, next: Self) -> bool {
+        if self == next {
+            return true;
+        }
+        match self {
+            Self::Ready => matches!(
+                next,
+                Self::Downloading | Self::Downloaded | Self::Error | Self::Skipped
+            ),
+            // back to `Ready` when recovering from an interrupted download
+            Self::Downloading => matches!(next, Self::Downloaded | Self::Error | Self::Ready),
+            Self::Downloaded => matches!(
+                next,
+                Self::Downloading | Self::Played | Self::Deleted | Self::Archived
+            ),
+            Self::Played => matches!(next, Self::Downloaded | Self::Deleted | Self::Archived),
+            Self::Error => matches!(next, Self::Ready | Self::Skipped),
+            Self::Skipped | Self::Deleted => matches!(next, Self::Ready),
+            Self::Archived => matches!(next, Self::Ready | Self::Deleted),
         }
     }
+
+    /// The statuses in `from` for a bulk update to `next`
+    /// # Errors
+    /// Return error if one of them can't move to `next`
+    pub fn transition_sources(from: &[Self], next: Self) -> Result<Vec<Self>, Error> {
+        if let Some(status) = from.iter().find(|s| !s.can_transition_to(next)) {
+            return Err(format_err!("Episodes can't go from {status} to {next}"));
+        }
+        Ok(from.to_vec())
+    }
 }
 
 impl fmt::Display for EpisodeStatus {
@@ -35,20 +103,18 @@ impl FromStr for EpisodeStatus {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "Ready" => Ok(Self::Ready),
+            "Downloading" => Ok(Self::Downloading),
             "Downloaded" => Ok(Self::Downloaded),
+            "Played" => Ok(Self::Played),
             "Error" => Ok(Self::Error),
             "Skipped" => Ok(Self::Skipped),
+            "Deleted" => Ok(Self::Deleted),
+            "Archived" => Ok(Self::Archived),
             _ => Err(format_err!("Invalid string {s}")),
         }
     }
 }
 
-impl Default for EpisodeStatus {
-    fn default() -> Self {
-        Self::Ready
-    }
-}
-
 impl<'a> FromSql<'a> for EpisodeStatus {
     fn from_sql(
         ty: &Type,
@@ -90,3 +156,52 @@ impl ToSql for EpisodeStatus {
         self.to_str().to_sql_checked(ty, out)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::episode_status::EpisodeStatus;
+
+    #[test]
+    fn test_episode_status_roundtrip() {
+        for status in EpisodeStatus::ALL {
+            assert_eq!(status.to_str().parse::<EpisodeStatus>().unwrap(), status);
+        }
+        assert!("Gone".parse::<EpisodeStatus>().is_err());
+    }
+
+    #[test]
+    fn test_episode_status_transitions() {
+        use EpisodeStatus::{Deleted, Downloaded, Downloading, Error, Played, Ready, Skipped};
+
+        for status in EpisodeStatus::ALL {
+            assert!(status.can_transition_to(status));
+        }
+        assert!(Ready.can_transition_to(Downloading));
+        assert!(Downloading.can_transition_to(Downloaded));
+        assert!(Downloading.can_transition_to(Ready));
+        assert!(Downloaded.can_transition_to(Played));
+        assert!(Played.can_transition_to(Deleted));
+        assert!(Error.can_transition_to(Ready));
+        assert!(!Error.can_transition_to(Downloaded));
+        assert!(!Skipped.can_transition_to(Downloading));
+        assert!(!Deleted.can_transition_to(Downloaded));
+        assert!(!Ready.can_transition_to(Played));
+    }
+
+    #[test]
+    fn test_episode_status_transition_sources() {
+        use EpisodeStatus::{Deleted, Downloaded, Downloading, Error, Played, Ready, Skipped};
+
+        assert_eq!(
+            EpisodeStatus::transition_sources(&[Error], Ready).unwrap(),
+            vec![Error]
+        );
+        assert_eq!(
+            EpisodeStatus::transition_sources(&[Downloading], Ready).unwrap(),
+            vec![Downloading]
+        );
+        assert!(EpisodeStatus::transition_sources(&[Error, Played], Ready).is_err());
+        assert!(EpisodeStatus::transition_sources(&[Deleted], Downloaded).is_err());
+        assert!(EpisodeStatus::transition_sources(&[Skipped], Downloading).is_err());
+    }
+}
@@ -4,28 +4,181 @@ use rand::{
     distr::{Distribution, Uniform},
     rng as thread_rng,
 };
-use reqwest::{Client, Response, Url};
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    Client, Response, StatusCode, Url,
+};
 use std::{convert::TryFrom, time::Duration};
+use time::OffsetDateTime;
 use tokio::time::sleep;
 
+use crate::feed::parse_pubdate;
+
+/// Longest delay between retries in seconds a config can ask for
+pub const MAX_RETRY_DELAY: f64 = 3600.0;
+
+/// Backoff parameters for retrying requests
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Delay before the first retry in seconds, doubled for every attempt
+    pub base_delay: f64,
+    /// Upper bound of the delay in seconds, a `Retry-After` asking for more
+    /// than this ends the retries
+    pub max_delay: f64,
+    /// Fraction of the delay added at random, between 0 and 1
+    pub jitter: f64,
+    /// Total number of attempts, including the first one
+    pub max_attempts: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            base_delay: 1.0,
+            max_delay: 64.0,
+            jitter: 0.5,
+            max_attempts: 6,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay after `attempt` failed attempts, `sample` is a random number in
+    /// `[0, 1)` scaling the jitter.  The delay never shrinks from one attempt
+    /// to the next and never exceeds `max_delay`.
+    #[must_use]
+    pub fn delay(&self, attempt: u32, sample: f64) -> Duration {
+        let exponent = i32::try_from(attempt.saturating_sub(1)).unwrap_or(i32::MAX);
+        let delay = (self.base_delay * 2f64.powi(exponent)).min(self.max_delay);
+        let delay = delay * (1.0 + self.jitter.clamp(0.0, 1.0) * sample);
+        Duration::from_secs_f64(delay.clamp(0.0, self.max_delay.max(0.0)))
+    }
+}
+
+/// Server errors and rate limiting are worth retrying, any other status
+/// (including the remaining 4xx codes) is final
+#[must_use]
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Delay requested by a `Retry-After` header, either in seconds or as an
+/// http date
+#[must_use]
+pub fn retry_after(headers: &HeaderMap, now: OffsetDateTime) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = parse_pubdate(value)?;
+    Some(Duration::try_from(date - now).unwrap_or(Duration::ZERO))
+}
+
 #[async_trait]
 pub trait ExponentialRetry {
     fn get_client(&self) -> &Client;
 
+    fn get_retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::default()
+    }
+
     async fn get(&self, url: &Url) -> Result<Response, Error> {
-        let mut timeout: f64 = 1.0;
+        self.get_with_headers(url, &HeaderMap::new()).await
+    }
+
+    async fn get_with_headers(&self, url: &Url, headers: &HeaderMap) -> Result<Response, Error> {
+        self.client_get(self.get_client(), url, headers).await
+    }
+
+    /// Send a GET request, retrying transport errors and retryable status
+    /// codes.  Once the attempts are used up the last response is returned
+    /// as is, so callers still need to check its status.
+    async fn client_get(
+        &self,
+        client: &Client,
+        url: &Url,
+        headers: &HeaderMap,
+    ) -> Result<Response, Error> {
+        let policy = self.get_retry_policy();
         let range = Uniform::try_from(0..1000)?;
+        let mut attempt = 0;
         loop {
-            match self.get_client().get(url.clone()).send().await {
-                Ok(resp) => return Ok(resp),
+            attempt += 1;
+            let sample = f64::from(range.sample(&mut thread_rng())) / 1000.0;
+            let delay = match client
+                .get(url.clone())
+                .headers(headers.clone())
+                .send()
+                .await
+            {
+                Ok(resp) => {
+                    if !is_retryable_status(resp.status()) || attempt >= policy.max_attempts {
+                        return Ok(resp);
+                    }
+                    match retry_after(resp.headers(), OffsetDateTime::now_utc()) {
+                        Some(delay) if delay.as_secs_f64() > policy.max_delay => return Ok(resp),
+                        Some(delay) => delay,
+                        None => policy.delay(attempt, sample),
+                    }
+                }
                 Err(err) => {
-                    sleep(Duration::from_millis((timeout * 1000.0) as u64)).await;
-                    timeout *= 4.0 * f64::from(range.sample(&mut thread_rng())) / 1000.0;
-                    if timeout >= 64.0 {
+                    if attempt >= policy.max_attempts {
                         return Err(err.into());
                     }
+                    policy.delay(attempt, sample)
                 }
-            }
+            };
+            sleep(delay).await;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use reqwest::{
+        header::{HeaderMap, RETRY_AFTER},
+        StatusCode,
+    };
+    use std::time::Duration;
+    use time::macros::datetime;
+
+    use crate::exponential_retry::{is_retryable_status, retry_after, RetryPolicy};
+
+    #[test]
+    fn test_retry_policy_delay() {
+        let policy = RetryPolicy::default();
+        let delays: Vec<_> = (1..=8).map(|a| policy.delay(a, 0.0)).collect();
+        assert_eq!(delays[0], Duration::from_secs(1));
+        assert_eq!(delays[3], Duration::from_secs(8));
+        assert_eq!(delays[7], Duration::from_secs(64));
+        assert!(delays.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(policy.delay(2, 0.5), Duration::from_millis(2500));
+        assert_eq!(policy.delay(10, 0.99), Duration::from_secs(64));
+    }
+
+    #[test]
+    fn test_is_retryable_status() {
+        assert!(is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_retryable_status(StatusCode::BAD_GATEWAY));
+        assert!(is_retryable_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!is_retryable_status(StatusCode::NOT_FOUND));
+        assert!(!is_retryable_status(StatusCode::FORBIDDEN));
+        assert!(!is_retryable_status(StatusCode::OK));
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let now = datetime!(2015-10-21 07:27:00 UTC);
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers, now), None);
+        headers.insert(RETRY_AFTER, "120".parse().unwrap());
+        assert_eq!(retry_after(&headers, now), Some(Duration::from_secs(120)));
+        headers.insert(
+            RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(retry_after(&headers, now), Some(Duration::from_secs(60)));
+        headers.insert(RETRY_AFTER, "soon".parse().unwrap());
+        assert_eq!(retry_after(&headers, now), None);
+    }
+}
@@ -0,0 +1,721 @@
+use anyhow::{format_err, Error};
+use roxmltree::{Document, Node};
+use stack_string::StackString;
+use time::{
+    format_description::well_known::{Rfc2822, Rfc3339},
+    OffsetDateTime,
+};
+
+const RSS_NAMESPACES: [&str; 2] = [
+    "http://purl.org/rss/1.0/",
+    "http://my.netscape.com/rdf/simple/0.9/",
+];
+const DC_NAMESPACE: &str = "http://purl.org/dc/elements/1.1/";
+const MEDIA_NAMESPACE: &str = "http://search.yahoo.com/mrss/";
+const ATOM_NAMESPACE: &str = "http://www.w3.org/2005/Atom";
+const ITUNES_NAMESPACE: &str = "http://www.itunes.com/dtds/podcast-1.0.dtd";
+const PODCAST_NAMESPACE: &str = "https://podcastindex.org/namespace/1.0";
+
+/// Metadata from the `itunes:` namespace of the channel
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct ItunesChannel {
+    pub author: Option<StackString>,
+    pub summary: Option<StackString>,
+    pub image: Option<StackString>,
+    pub explicit: Option<bool>,
+    pub block: bool,
+    pub new_feed_url: Option<StackString>,
+}
+
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct FeedChapters {
+    pub url: StackString,
+    pub chaptype: Option<StackString>,
+}
+
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct FeedTranscript {
+    pub url: StackString,
+    pub transtype: Option<StackString>,
+    pub language: Option<StackString>,
+    pub rel: Option<StackString>,
+}
+
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct FeedPerson {
+    pub name: StackString,
+    pub role: Option<StackString>,
+    pub group: Option<StackString>,
+    pub img: Option<StackString>,
+    pub href: Option<StackString>,
+}
+
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct FeedFunding {
+    pub url: StackString,
+    pub message: Option<StackString>,
+}
+
+/// Metadata from the Podcasting 2.0 `podcast:` namespace of the channel
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct PodcastChannel {
+    pub guid: Option<StackString>,
+    pub locked: Option<bool>,
+    pub funding: Vec<FeedFunding>,
+    pub persons: Vec<FeedPerson>,
+}
+
+/// Metadata from the Podcasting 2.0 `podcast:` namespace of an item
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct PodcastItem {
+    pub chapters: Option<FeedChapters>,
+    pub transcripts: Vec<FeedTranscript>,
+    pub persons: Vec<FeedPerson>,
+}
+
+/// Metadata from the `itunes:` namespace of an item
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct ItunesItem {
+    pub duration: Option<i32>,
+    pub episode: Option<i32>,
+    pub season: Option<i32>,
+    pub episode_type: Option<StackString>,
+    pub explicit: Option<bool>,
+    pub image: Option<StackString>,
+    pub author: Option<StackString>,
+    pub summary: Option<StackString>,
+    pub block: bool,
+}
+
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct FeedChannel {
+    pub title: Option<StackString>,
+    pub link: Option<StackString>,
+    pub description: Option<StackString>,
+    pub image: Option<StackString>,
+    pub itunes: ItunesChannel,
+    pub podcast: PodcastChannel,
+}
+
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct FeedEnclosure {
+    pub url: StackString,
+    pub enctype: Option<StackString>,
+    pub length: Option<i64>,
+}
+
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct FeedItem {
+    pub title: Option<StackString>,
+    pub guid: Option<StackString>,
+    pub enclosure: Option<FeedEnclosure>,
+    pub pubdate: Option<OffsetDateTime>,
+    pub description: Option<StackString>,
+    pub link: Option<StackString>,
+    pub itunes: ItunesItem,
+    pub podcast: PodcastItem,
+}
+
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct Feed {
+    pub channel: FeedChannel,
+    pub items: Vec<FeedItem>,
+}
+
+/// Match elements of the core RSS vocabulary, these are un-namespaced in RSS
+/// 0.9x/2.0 and live in the default namespace in RSS 0.90/1.0
+fn is_rss(node: &Node, name: &str) -> bool {
+    node.is_element()
+        && node.tag_name().name() == name
+        && node
+            .tag_name()
+            .namespace()
+            .is_none_or(|ns| RSS_NAMESPACES.contains(&ns))
+}
+
+fn is_ns(node: &Node, namespace: &str, name: &str) -> bool {
+    node.is_element()
+        && node.tag_name().name() == name
+        && node.tag_name().namespace() == Some(namespace)
+}
+
+fn node_text(node: &Node) -> Option<StackString> {
+    let text: String = node
+        .descendants()
+        .filter(Node::is_text)
+        .filter_map(|n| n.text())
+        .collect();
+    let text = text.trim();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text.into())
+    }
+}
+
+fn child_text(node: &Node, name: &str) -> Option<StackString> {
+    node.children()
+        .find(|n| is_rss(n, name))
+        .and_then(|n| node_text(&n))
+}
+
+fn atom_text(node: &Node, name: &str) -> Option<StackString> {
+    node.children()
+        .find(|n| is_ns(n, ATOM_NAMESPACE, name))
+        .and_then(|n| node_text(&n))
+}
+
+/// Some feeds capitalize the itunes namespace URI
+fn is_itunes(node: &Node, name: &str) -> bool {
+    node.is_element()
+        && node.tag_name().name() == name
+        && node
+            .tag_name()
+            .namespace()
+            .is_some_and(|ns| ns.eq_ignore_ascii_case(ITUNES_NAMESPACE))
+}
+
+fn itunes_node<'a, 'input>(node: &Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|n| is_itunes(n, name))
+}
+
+fn itunes_text(node: &Node, name: &str) -> Option<StackString> {
+    itunes_node(node, name).and_then(|n| node_text(&n))
+}
+
+fn itunes_flag(node: &Node, name: &str) -> Option<bool> {
+    itunes_text(node, name).and_then(|t| match t.to_lowercase().as_str() {
+        "yes" | "true" | "explicit" => Some(true),
+        "no" | "false" | "clean" => Some(false),
+        _ => None,
+    })
+}
+
+/// Parse `itunes:duration`, which is either a number of seconds or
+/// `[HH:]MM:SS`
+#[must_use]
+pub fn parse_duration(s: &str) -> Option<i32> {
+    let s = s.trim();
+    if s.is_empty() {
+        return None;
+    }
+    let mut seconds = 0.0;
+    for part in s.split(':') {
+        let value: f64 = part.trim().parse().ok()?;
+        if value < 0.0 {
+            return None;
+        }
+        seconds = seconds * 60.0 + value;
+    }
+    Some(seconds.round() as i32)
+}
+
+impl ItunesChannel {
+    fn from_node(node: &Node) -> Self {
+        Self {
+            author: itunes_text(node, "author"),
+            summary: itunes_text(node, "summary"),
+            image: itunes_node(node, "image")
+                .and_then(|n| n.attribute("href"))
+                .map(Into::into),
+            explicit: itunes_flag(node, "explicit"),
+            block: itunes_flag(node, "block").unwrap_or(false),
+            new_feed_url: itunes_text(node, "new-feed-url"),
+        }
+    }
+}
+
+impl ItunesItem {
+    fn from_node(node: &Node) -> Self {
+        Self {
+            duration: itunes_text(node, "duration").and_then(|d| parse_duration(&d)),
+            episode: itunes_text(node, "episode").and_then(|e| e.parse().ok()),
+            season: itunes_text(node, "season").and_then(|e| e.parse().ok()),
+            episode_type: itunes_text(node, "episodeType").map(|t| t.to_lowercase().into()),
+            explicit: itunes_flag(node, "explicit"),
+            image: itunes_node(node, "image")
+                .and_then(|n| n.attribute("href"))
+                .map(Into::into),
+            author: itunes_text(node, "author"),
+            summary: itunes_text(node, "summary"),
+            block: itunes_flag(node, "block").unwrap_or(false),
+        }
+    }
+
+    /// Trailers and bonus content are flagged with `itunes:episodeType`
+    #[must_use]
+    pub fn is_full_episode(&self) -> bool {
+        self.episode_type
+            .as_ref()
+            .is_none_or(|t| t.as_str() == "full")
+    }
+}
+
+fn podcast_nodes<'a, 'input: 'a>(
+    node: &Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |n| is_ns(n, PODCAST_NAMESPACE, name))
+}
+
+fn attribute(node: &Node, name: &str) -> Option<StackString> {
+    node.attribute(name)
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .map(Into::into)
+}
+
+impl FeedPerson {
+    fn from_node(node: &Node) -> Option<Self> {
+        Some(Self {
+            name: node_text(node)?,
+            role: attribute(node, "role").map(|r| r.to_lowercase().into()),
+            group: attribute(node, "group").map(|g| g.to_lowercase().into()),
+            img: attribute(node, "img"),
+            href: attribute(node, "href"),
+        })
+    }
+
+    fn from_parent(node: &Node) -> Vec<Self> {
+        podcast_nodes(node, "person")
+            .filter_map(|n| Self::from_node(&n))
+            .collect()
+    }
+}
+
+impl PodcastChannel {
+    fn from_node(node: &Node) -> Self {
+        Self {
+            guid: podcast_nodes(node, "guid").find_map(|n| node_text(&n)),
+            locked: podcast_nodes(node, "locked")
+                .find_map(|n| node_text(&n))
+                .and_then(|t| match t.to_lowercase().as_str() {
+                    "yes" | "true" => Some(true),
+                    "no" | "false" => Some(false),
+                    _ => None,
+                }),
+            funding: podcast_nodes(node, "funding")
+                .filter_map(|n| {
+                    Some(FeedFunding {
+                        url: attribute(&n, "url")?,
+                        message: node_text(&n),
+                    })
+                })
+                .collect(),
+            persons: FeedPerson::from_parent(node),
+        }
+    }
+}
+
+impl PodcastItem {
+    /// True if the item carries none of the `podcast:` namespace metadata
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.chapters.is_none() && self.transcripts.is_empty() && self.persons.is_empty()
+    }
+
+    fn from_node(node: &Node) -> Self {
+        Self {
+            chapters: podcast_nodes(node, "chapters").find_map(|n| {
+                Some(FeedChapters {
+                    url: attribute(&n, "url")?,
+                    chaptype: attribute(&n, "type"),
+                })
+            }),
+            transcripts: podcast_nodes(node, "transcript")
+                .filter_map(|n| {
+                    Some(FeedTranscript {
+                        url: attribute(&n, "url")?,
+                        transtype: attribute(&n, "type"),
+                        language: attribute(&n, "language"),
+                        rel: attribute(&n, "rel"),
+                    })
+                })
+                .collect(),
+            persons: FeedPerson::from_parent(node),
+        }
+    }
+}
+
+/// Atom links without a `rel` attribute are `alternate` links
+fn atom_links<'a, 'input: 'a>(
+    node: &Node<'a, 'input>,
+    rel: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children().filter(move |n| {
+        is_ns(n, ATOM_NAMESPACE, "link") && n.attribute("rel").unwrap_or("alternate") == rel
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeedFormat {
+    Rss,
+    Atom,
+}
+
+impl FeedFormat {
+    /// # Errors
+    /// Return error if the root element is neither RSS nor Atom
+    pub fn detect(doc: &Document) -> Result<Self, Error> {
+        let root = doc.root_element();
+        match root.tag_name().name() {
+            "rss" | "RDF" => Ok(Self::Rss),
+            "feed" if root.tag_name().namespace() == Some(ATOM_NAMESPACE) => Ok(Self::Atom),
+            name => Err(format_err!("Unknown feed format {name}")),
+        }
+    }
+}
+
+/// Parse the date formats seen in the wild, RFC 2822 for `pubDate` and RFC
+/// 3339 for `dc:date`
+#[must_use]
+pub fn parse_pubdate(s: &str) -> Option<OffsetDateTime> {
+    let s = s.trim();
+    OffsetDateTime::parse(s, &Rfc2822)
+        .or_else(|_| OffsetDateTime::parse(s, &Rfc3339))
+        .ok()
+}
+
+impl FeedEnclosure {
+    fn from_node(node: &Node) -> Option<Self> {
+        let url = node.attribute("url")?.trim();
+        if url.is_empty() {
+            return None;
+        }
+        Some(Self {
+            url: url.into(),
+            enctype: node.attribute("type").map(Into::into),
+            length: node.attribute("length").and_then(|l| l.trim().parse().ok()),
+        })
+    }
+}
+
+impl FeedItem {
+    fn from_rss_node(node: &Node) -> Self {
+        let enclosure = node
+            .children()
+            .filter(|n| is_rss(n, "enclosure"))
+            .find_map(|n| FeedEnclosure::from_node(&n))
+            .or_else(|| {
+                node.descendants()
+                    .filter(|n| is_ns(n, MEDIA_NAMESPACE, "content"))
+                    .find_map(|n| FeedEnclosure::from_node(&n))
+            });
+        let pubdate = child_text(node, "pubDate")
+            .or_else(|| {
+                node.children()
+                    .find(|n| is_ns(n, DC_NAMESPACE, "date"))
+                    .and_then(|n| node_text(&n))
+            })
+            .and_then(|d| parse_pubdate(&d));
+        Self {
+            title: child_text(node, "title"),
+            guid: child_text(node, "guid"),
+            enclosure,
+            pubdate,
+            description: child_text(node, "description"),
+            link: child_text(node, "link"),
+            itunes: ItunesItem::from_node(node),
+            podcast: PodcastItem::from_node(node),
+        }
+    }
+
+    fn from_atom_node(node: &Node) -> Self {
+        let enclosure = atom_links(node, "enclosure").find_map(|n| {
+            let url = n.attribute("href")?.trim();
+            if url.is_empty() {
+                return None;
+            }
+            Some(FeedEnclosure {
+                url: url.into(),
+                enctype: n.attribute("type").map(Into::into),
+                length: n.attribute("length").and_then(|l| l.trim().parse().ok()),
+            })
+        });
+        Self {
+            title: atom_text(node, "title"),
+            guid: atom_text(node, "id"),
+            enclosure,
+            pubdate: atom_text(node, "published")
+                .or_else(|| atom_text(node, "updated"))
+                .and_then(|d| parse_pubdate(&d)),
+            description: atom_text(node, "summary").or_else(|| atom_text(node, "content")),
+            link: atom_links(node, "alternate")
+                .find_map(|n| n.attribute("href"))
+                .map(Into::into),
+            itunes: ItunesItem::from_node(node),
+            podcast: PodcastItem::from_node(node),
+        }
+    }
+}
+
+impl Feed {
+    /// Parse either an RSS or an Atom feed, the format is taken from the root
+    /// element
+    /// # Errors
+    /// Return error if the text is not an RSS or Atom document
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let doc = Document::parse(text).map_err(|e| format_err!("{e:?}"))?;
+        match FeedFormat::detect(&doc)? {
+            FeedFormat::Rss => Self::from_rss_document(&doc),
+            FeedFormat::Atom => Self::from_atom_document(&doc),
+        }
+    }
+
+    /// # Errors
+    /// Return error if the text is not an RSS document
+    pub fn parse_rss(text: &str) -> Result<Self, Error> {
+        let doc = Document::parse(text).map_err(|e| format_err!("{e:?}"))?;
+        Self::from_rss_document(&doc)
+    }
+
+    /// # Errors
+    /// Return error if the text is not an Atom document
+    pub fn parse_atom(text: &str) -> Result<Self, Error> {
+        let doc = Document::parse(text).map_err(|e| format_err!("{e:?}"))?;
+        Self::from_atom_document(&doc)
+    }
+
+    fn from_rss_document(doc: &Document) -> Result<Self, Error> {
+        let root = doc.root_element();
+        let channel_node = root
+            .children()
+            .find(|n| is_rss(n, "channel"))
+            .ok_or_else(|| format_err!("No channel element"))?;
+        let channel = FeedChannel {
+            title: child_text(&channel_node, "title"),
+            link: child_text(&channel_node, "link"),
+            description: child_text(&channel_node, "description"),
+            image: channel_node
+                .children()
+                .find(|n| is_rss(n, "image"))
+                .and_then(|n| child_text(&n, "url")),
+            itunes: ItunesChannel::from_node(&channel_node),
+            podcast: PodcastChannel::from_node(&channel_node),
+        };
+        // RSS 2.0 nests items in the channel, RSS 1.0 makes them siblings
+        let items = channel_node
+            .children()
+            .chain(root.children())
+            .filter(|n| is_rss(n, "item"))
+            .map(|n| FeedItem::from_rss_node(&n))
+            .collect();
+        Ok(Self { channel, items })
+    }
+
+    fn from_atom_document(doc: &Document) -> Result<Self, Error> {
+        let root = doc.root_element();
+        if !is_ns(&root, ATOM_NAMESPACE, "feed") {
+            return Err(format_err!("No feed element"));
+        }
+        let channel = FeedChannel {
+            title: atom_text(&root, "title"),
+            link: atom_links(&root, "alternate")
+                .find_map(|n| n.attribute("href"))
+                .map(Into::into),
+            description: atom_text(&root, "subtitle"),
+            image: atom_text(&root, "logo").or_else(|| atom_text(&root, "icon")),
+            itunes: ItunesChannel::from_node(&root),
+            podcast: PodcastChannel::from_node(&root),
+        };
+        let items = root
+            .children()
+            .filter(|n| is_ns(n, ATOM_NAMESPACE, "entry"))
+            .map(|n| FeedItem::from_atom_node(&n))
+            .collect();
+        Ok(Self { channel, items })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use time::macros::datetime;
+
+    use crate::feed::{parse_duration, parse_pubdate, Feed, PodcastItem};
+
+    const RSS_FEED: &str = include_str!("../tests/data/rss_feed.xml");
+    const ATOM_FEED: &str = include_str!("../tests/data/atom_feed.xml");
+    const ITUNES_FEED: &str = include_str!("../tests/data/itunes_feed.xml");
+    const PODCAST_FEED: &str = include_str!("../tests/data/podcast_namespace_feed.xml");
+
+    #[test]
+    fn test_parse_rss() -> Result<(), Error> {
+        let feed = Feed::parse_rss(RSS_FEED)?;
+        assert_eq!(feed.channel.title.as_deref(), Some("Test Show"));
+        assert_eq!(
+            feed.channel.image.as_deref(),
+            Some("https://example.com/cover.jpg")
+        );
+        assert_eq!(feed.items.len(), 3);
+
+        let item = &feed.items[0];
+        assert_eq!(item.title.as_deref(), Some("Episode 2"));
+        assert_eq!(item.guid.as_deref(), Some("ep-0002"));
+        assert_eq!(item.description.as_deref(), Some("<p>Second</p>"));
+        assert_eq!(item.link.as_deref(), Some("https://example.com/2"));
+        assert_eq!(item.pubdate, Some(datetime!(2024-01-02 10:00:00 UTC)));
+        let enclosure = item.enclosure.as_ref().unwrap();
+        assert_eq!(&enclosure.url, "https://example.com/2.mp3");
+        assert_eq!(enclosure.enctype.as_deref(), Some("audio/mpeg"));
+        assert_eq!(enclosure.length, Some(1234));
+
+        let item = &feed.items[1];
+        assert_eq!(item.title.as_deref(), Some("Episode 1"));
+        assert_eq!(item.pubdate, Some(datetime!(2024-01-01 09:30:00 -5)));
+        let enclosure = item.enclosure.as_ref().unwrap();
+        assert_eq!(&enclosure.url, "https://example.com/1.m4a");
+        assert_eq!(enclosure.length, None);
+
+        assert!(feed.items[2].enclosure.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_atom() -> Result<(), Error> {
+        let feed = Feed::parse_atom(ATOM_FEED)?;
+        assert_eq!(feed.channel.title.as_deref(), Some("Atom Show"));
+        assert_eq!(feed.channel.link.as_deref(), Some("https://example.org/"));
+        assert_eq!(feed.channel.description.as_deref(), Some("Only Atom here"));
+        assert_eq!(feed.items.len(), 2);
+
+        let item = &feed.items[0];
+        assert_eq!(item.title.as_deref(), Some("Atom Episode 1"));
+        assert_eq!(item.guid.as_deref(), Some("urn:uuid:atom-ep-1"));
+        assert_eq!(item.link.as_deref(), Some("https://example.org/ep1"));
+        assert_eq!(item.description.as_deref(), Some("First atom episode"));
+        assert_eq!(item.pubdate, Some(datetime!(2024-03-01 12:00:00 UTC)));
+        let enclosure = item.enclosure.as_ref().unwrap();
+        assert_eq!(&enclosure.url, "https://cdn.example.org/ep1.ogg");
+        assert_eq!(enclosure.enctype.as_deref(), Some("audio/ogg"));
+        assert_eq!(enclosure.length, Some(5678));
+
+        let item = &feed.items[1];
+        assert_eq!(item.pubdate, Some(datetime!(2024-02-01 08:00:00 +1)));
+        assert!(item.enclosure.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_detects_format() -> Result<(), Error> {
+        assert_eq!(Feed::parse(RSS_FEED)?, Feed::parse_rss(RSS_FEED)?);
+        assert_eq!(Feed::parse(ATOM_FEED)?, Feed::parse_atom(ATOM_FEED)?);
+        assert!(Feed::parse("<html><body/></html>").is_err());
+        assert!(Feed::parse_rss(ATOM_FEED).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_itunes() -> Result<(), Error> {
+        let feed = Feed::parse(ITUNES_FEED)?;
+        let itunes = &feed.channel.itunes;
+        assert_eq!(itunes.author.as_deref(), Some("Some Author"));
+        assert_eq!(itunes.summary.as_deref(), Some("A seasonal show"));
+        assert_eq!(
+            itunes.image.as_deref(),
+            Some("https://example.net/show.jpg")
+        );
+        assert_eq!(itunes.explicit, Some(false));
+        assert!(!itunes.block);
+        assert_eq!(
+            itunes.new_feed_url.as_deref(),
+            Some("https://new.example.net/feed.xml")
+        );
+
+        let itunes = &feed.items[0].itunes;
+        assert_eq!(itunes.duration, Some(3723));
+        assert_eq!(itunes.season, Some(2));
+        assert_eq!(itunes.episode, Some(5));
+        assert_eq!(itunes.episode_type.as_deref(), Some("full"));
+        assert_eq!(itunes.explicit, Some(true));
+        assert_eq!(
+            itunes.image.as_deref(),
+            Some("https://example.net/s2e5.jpg")
+        );
+        assert!(itunes.is_full_episode());
+
+        let itunes = &feed.items[1].itunes;
+        assert_eq!(itunes.duration, Some(95));
+        assert_eq!(itunes.episode_type.as_deref(), Some("trailer"));
+        assert!(itunes.block);
+        assert!(!itunes.is_full_episode());
+
+        let itunes = &feed.items[2].itunes;
+        assert_eq!(itunes.duration, Some(1800));
+        assert_eq!(itunes.episode_type, None);
+        assert!(itunes.is_full_episode());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_podcast_namespace() -> Result<(), Error> {
+        let feed = Feed::parse(PODCAST_FEED)?;
+        let podcast = &feed.channel.podcast;
+        assert_eq!(
+            podcast.guid.as_deref(),
+            Some("917393e3-1b1e-5cef-ace4-edaa54e1f810")
+        );
+        assert_eq!(podcast.locked, Some(true));
+        assert_eq!(podcast.funding.len(), 1);
+        assert_eq!(&podcast.funding[0].url, "https://example.com/donate");
+        assert_eq!(podcast.funding[0].message.as_deref(), Some("Support us"));
+        assert_eq!(podcast.persons.len(), 1);
+        assert_eq!(&podcast.persons[0].name, "Jane Host");
+        assert_eq!(podcast.persons[0].role.as_deref(), Some("host"));
+
+        let podcast = &feed.items[0].podcast;
+        let chapters = podcast.chapters.as_ref().unwrap();
+        assert_eq!(&chapters.url, "https://example.com/ep1/chapters.json");
+        assert_eq!(
+            chapters.chaptype.as_deref(),
+            Some("application/json+chapters")
+        );
+        assert_eq!(podcast.transcripts.len(), 2);
+        assert_eq!(
+            &podcast.transcripts[0].url,
+            "https://example.com/ep1/transcript.vtt"
+        );
+        assert_eq!(
+            podcast.transcripts[0].transtype.as_deref(),
+            Some("text/vtt")
+        );
+        assert_eq!(podcast.transcripts[1].language.as_deref(), Some("es"));
+        assert_eq!(podcast.transcripts[1].rel.as_deref(), Some("captions"));
+        assert_eq!(podcast.persons.len(), 1);
+        assert_eq!(&podcast.persons[0].name, "Guest Person");
+        assert_eq!(
+            podcast.persons[0].href.as_deref(),
+            Some("https://example.com/guest")
+        );
+
+        assert!(!podcast.is_empty());
+
+        assert_eq!(feed.items[1].podcast, PodcastItem::default());
+        assert!(feed.items[1].podcast.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("1:02:03"), Some(3723));
+        assert_eq!(parse_duration("45:30"), Some(2730));
+        assert_eq!(parse_duration("1800"), Some(1800));
+        assert_eq!(parse_duration("94.6"), Some(95));
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("about an hour"), None);
+    }
+
+    #[test]
+    fn test_parse_pubdate() {
+        assert_eq!(
+            parse_pubdate("Wed, 07 Feb 2024 05:00:00 EST"),
+            Some(datetime!(2024-02-07 05:00:00 -5))
+        );
+        assert_eq!(
+            parse_pubdate("2024-02-07T05:00:00Z"),
+            Some(datetime!(2024-02-07 05:00:00 UTC))
+        );
+        assert_eq!(parse_pubdate("last tuesday"), None);
+    }
+}
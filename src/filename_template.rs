@@ -0,0 +1,313 @@
+use anyhow::{format_err, Error};
+use stack_string::{format_sstr, StackString};
+use std::{
+    collections::HashSet,
+    path::Path,
+    sync::{Mutex, PoisonError},
+};
+use time::macros::format_description;
+
+use crate::{episode::Episode, media_type::extension_for_content_type};
+
+/// Longest file name produced, well below the 255 byte limit of most
+/// filesystems to leave room for `.part` and collision suffixes
+const MAX_FILENAME_LEN: usize = 200;
+
+const SEPARATORS: [char; 3] = ['_', '-', '.'];
+
+const PLACEHOLDERS: [&str; 7] = [
+    "title",
+    "date",
+    "season",
+    "episode",
+    "episodeid",
+    "basename",
+    "ext",
+];
+
+/// Split a template like `{date}_{title}.{ext}` into literal text and
+/// placeholder names
+fn parse_template(template: &str) -> Result<Vec<(bool, &str)>, Error> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            parts.push((false, &rest[..start]));
+        }
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format_err!("Unclosed placeholder in {template}"))?;
+        let name = &rest[start + 1..start + end];
+        if !PLACEHOLDERS.contains(&name) {
+            return Err(format_err!("Unknown placeholder {{{name}}} in {template}"));
+        }
+        parts.push((true, name));
+        rest = &rest[start + end + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push((false, rest));
+    }
+    Ok(parts)
+}
+
+/// Check that a template only uses known placeholders
+/// # Errors
+/// Return error if the template is malformed
+pub fn validate_template(template: &str) -> Result<(), Error> {
+    parse_template(template).map(|_| ())
+}
+
+/// Extension of the enclosure url, `mp3` if it doesn't have a sensible one.
+/// Only used when the enclosure type isn't a known media type, the
+/// extension is corrected once the file has been downloaded.
+fn url_extension(basename: &str) -> StackString {
+    Path::new(basename)
+        .extension()
+        .and_then(|e| e.to_str())
+        .filter(|e| !e.is_empty() && e.len() <= 5 && e.chars().all(char::is_alphanumeric))
+        .map_or_else(|| "mp3".into(), |e| e.to_lowercase().into())
+}
+
+/// Lowercase ascii letters and digits of a title, with spaces turned into
+/// `_`
+#[must_use]
+pub fn basename_filter(title: &str) -> String {
+    title
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            'a'..='z' | '0'..='9' => Some(c),
+            ' ' => Some('_'),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether a file name can be used as is, [`sanitize_filename`] would change
+/// it otherwise
+fn is_safe_filename(filename: &str) -> bool {
+    !filename.is_empty()
+        && filename.len() <= MAX_FILENAME_LEN
+        && !filename.starts_with('.')
+        && filename
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || SEPARATORS.contains(&c))
+}
+
+/// Render the file name of an episode.  Without a template the name is
+/// [`Episode::default_basename`], kept as is unless it isn't safe to use.
+/// # Errors
+/// Return error if the template is malformed or the url can't be parsed
+pub fn render_filename(template: Option<&str>, epi: &Episode) -> Result<StackString, Error> {
+    let Some(template) = template else {
+        let basename = epi.default_basename()?;
+        if is_safe_filename(&basename) {
+            return Ok(basename);
+        }
+        return Ok(sanitize_filename(&basename));
+    };
+    let basename = epi.url_basename()?;
+    let mut filename = String::new();
+    for (is_placeholder, part) in parse_template(template)? {
+        if !is_placeholder {
+            filename.push_str(part);
+            continue;
+        }
+        let value: StackString = match part {
+            "title" => basename_filter(&epi.title).into(),
+            "date" => match epi.pubdate {
+                Some(pubdate) => pubdate
+                    .format(format_description!("[year]-[month]-[day]"))?
+                    .into(),
+                None => "".into(),
+            },
+            "season" => epi
+                .season_number
+                .map_or_else(|| "".into(), |s| format_sstr!("{s}")),
+            "episode" => epi
+                .episode_number
+                .map_or_else(|| "".into(), |e| format_sstr!("{e:02}")),
+            "episodeid" => format_sstr!("{}", epi.episodeid),
+            "basename" => Path::new(basename.as_str())
+                .file_stem()
+                .map_or_else(|| basename.clone(), |s| s.to_string_lossy().into()),
+            "ext" => extension_for_content_type(&epi.enctype)
+                .map_or_else(|| url_extension(&basename), Into::into),
+            _ => unreachable!(),
+        };
+        filename.push_str(&value);
+    }
+    Ok(sanitize_filename(&filename))
+}
+
+/// Make a file name safe to use on any filesystem: anything but ascii
+/// alphanumerics, `.`, `-` and `_` becomes `_`, runs of separators left by
+/// empty placeholders are collapsed and the length is bounded
+#[must_use]
+pub fn sanitize_filename(filename: &str) -> StackString {
+    let mut sanitized = String::with_capacity(filename.len());
+    for c in filename.chars() {
+        let c = if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+            c
+        } else {
+            '_'
+        };
+        if c == '_' && sanitized.ends_with(SEPARATORS) {
+            continue;
+        }
+        if (c == '.' || c == '-') && sanitized.ends_with('_') {
+            sanitized.pop();
+        }
+        sanitized.push(c);
+    }
+    let sanitized = sanitized.trim_end_matches(SEPARATORS);
+    let (stem, ext) = match sanitized.rfind('.') {
+        Some(idx) if sanitized.len() - idx <= 6 => sanitized.split_at(idx),
+        _ => (sanitized, ""),
+    };
+    let stem = stem.trim_matches(SEPARATORS);
+    let stem = if stem.is_empty() { "episode" } else { stem };
+    let stem = &stem[..stem.len().min(MAX_FILENAME_LEN - ext.len())];
+    format_sstr!("{stem}{ext}")
+}
+
+/// Append `_2`, `_3`, ... to the stem of `filename` until it neither is in
+/// `taken` nor exists in `directory`, the chosen name is added to `taken`
+pub fn unique_filename(
+    filename: &str,
+    directory: &Path,
+    taken: &mut HashSet<StackString>,
+) -> StackString {
+    let (stem, ext) = match filename.rfind('.') {
+        Some(idx) if idx > 0 => filename.split_at(idx),
+        _ => (filename, ""),
+    };
+    let mut candidate: StackString = filename.into();
+    let mut counter = 1;
+    while taken.contains(&candidate) || directory.join(candidate.as_str()).exists() {
+        counter += 1;
+        candidate = format_sstr!("{stem}_{counter}{ext}");
+    }
+    taken.insert(candidate.clone());
+    candidate
+}
+
+/// File names taken in a podcast directory, shared by the downloads running
+/// at the same time
+pub type ReservedNames = Mutex<HashSet<StackString>>;
+
+/// [`unique_filename`] for a download that may run alongside others in the
+/// same directory
+pub fn reserve_filename(filename: &str, directory: &Path, reserved: &ReservedNames) -> StackString {
+    let mut taken = reserved.lock().unwrap_or_else(PoisonError::into_inner);
+    unique_filename(filename, directory, &mut taken)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use std::{collections::HashSet, path::Path};
+    use time::macros::datetime;
+
+    use crate::{
+        episode::Episode,
+        filename_template::{
+            render_filename, reserve_filename, sanitize_filename, unique_filename,
+            validate_template, ReservedNames,
+        },
+    };
+
+    fn episode() -> Episode {
+        Episode {
+            episodeid: 42,
+            title: "Café: The \"Big\" One?".into(),
+            epurl: "https://cdn.example.com/shows/ep42.m4a?tracking=1".into(),
+            pubdate: Some(datetime!(2024-03-05 12:00 UTC)),
+            season_number: Some(2),
+            episode_number: Some(7),
+            ..Episode::default()
+        }
+    }
+
+    #[test]
+    fn test_render_filename() -> Result<(), Error> {
+        let epi = episode();
+        assert_eq!(&render_filename(None, &epi)?, "ep42.m4a");
+        assert_eq!(
+            &render_filename(Some("{date}_{season}x{episode}_{title}.{ext}"), &epi)?,
+            "2024-03-05_2x07_caf_the_big_one.m4a"
+        );
+        assert_eq!(
+            &render_filename(Some("{episodeid}-{basename}.{ext}"), &epi)?,
+            "42-ep42.m4a"
+        );
+        let epi = Episode {
+            enctype: "audio/mpeg".into(),
+            ..epi
+        };
+        assert_eq!(
+            &render_filename(Some("{basename}.{ext}"), &epi)?,
+            "ep42.mp3"
+        );
+        let epi = Episode {
+            season_number: None,
+            ..epi
+        };
+        assert_eq!(
+            &render_filename(Some("{season}x{episode}_{title}.{ext}"), &epi)?,
+            "x07_caf_the_big_one.mp3"
+        );
+        // without a template the names from before templates are kept
+        let epi = Episode {
+            title: "Part 1 - The Big One!".into(),
+            epurl: "https://feeds.acast.com/public/streams/show/episodes/media.mp3".into(),
+            ..epi
+        };
+        assert_eq!(&render_filename(None, &epi)?, "part_1__the_big_one.mp3");
+        assert_eq!(
+            &render_filename(Some("{title}.{ext}"), &epi)?,
+            "part_1_the_big_one.mp3"
+        );
+        let epi = Episode {
+            epurl: "https://cdn.example.com/newrustacean/bonus/11.mp3".into(),
+            ..epi
+        };
+        assert_eq!(&render_filename(None, &epi)?, "bonus_11.mp3");
+        let epi = Episode {
+            epurl: "https://cdn.example.com/shows/ep%2042.mp3".into(),
+            ..epi
+        };
+        assert_eq!(&render_filename(None, &epi)?, "ep_2042.mp3");
+        assert!(validate_template("{date}_{nope}.{ext}").is_err());
+        assert!(validate_template("{date").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sanitize_filename() {
+        assert_eq!(&sanitize_filename("../../etc/passwd"), "etc_passwd");
+        assert_eq!(&sanitize_filename(" a  b / c .mp3"), "a_b_c.mp3");
+        assert_eq!(&sanitize_filename("???.mp3"), "episode.mp3");
+        assert_eq!(&sanitize_filename(""), "episode");
+        let long = "x".repeat(300);
+        let sanitized = sanitize_filename(&format!("{long}.mp3"));
+        assert_eq!(sanitized.len(), 200);
+        assert!(sanitized.ends_with(".mp3"));
+    }
+
+    #[test]
+    fn test_unique_filename() {
+        let directory = Path::new("/nonexistent/podcast");
+        let mut taken = HashSet::new();
+        assert_eq!(&unique_filename("a.mp3", directory, &mut taken), "a.mp3");
+        assert_eq!(&unique_filename("a.mp3", directory, &mut taken), "a_2.mp3");
+        assert_eq!(&unique_filename("a.mp3", directory, &mut taken), "a_3.mp3");
+        assert_eq!(&unique_filename("b", directory, &mut taken), "b");
+        assert_eq!(&unique_filename("b", directory, &mut taken), "b_2");
+
+        let reserved = ReservedNames::new(taken);
+        assert_eq!(&reserve_filename("a.m4a", directory, &reserved), "a.m4a");
+        assert_eq!(&reserve_filename("a.mp3", directory, &reserved), "a_4.mp3");
+        assert_eq!(&reserve_filename("a.m4a", directory, &reserved), "a_2.m4a");
+    }
+}
@@ -11,12 +11,20 @@
 
 pub mod config;
 pub mod episode;
+pub mod episode_index;
 pub mod episode_status;
 pub mod exponential_retry;
+pub mod feed;
+pub mod filename_template;
+pub mod listing;
+pub mod media_type;
 pub mod pgpool;
 pub mod pod_connection;
 pub mod podcast;
+pub mod podcast_namespace;
 pub mod podcatch_opts;
+pub mod retention;
+pub mod work_queue;
 
 use anyhow::Error;
 use checksums::{hash_reader, Algorithm};
@@ -0,0 +1,426 @@
+use anyhow::{format_err, Error};
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+use stack_string::{format_sstr, StackString};
+use std::cmp::Ordering;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{episode::Episode, podcast::Podcast};
+
+/// How podcast and episode listings are printed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Aligned columns with a header
+    #[default]
+    Table,
+    /// A single json array of objects
+    Json,
+    /// One json object per line
+    Jsonl,
+    /// Comma separated values with a header
+    Csv,
+}
+
+/// A row of a listing, each column is rendered as a json value
+pub trait Listing {
+    /// Every column, in the order they are printed
+    const COLUMNS: &'static [&'static str];
+    /// Columns of a table when none are chosen, the other formats print
+    /// every column by default
+    const DEFAULT_COLUMNS: &'static [&'static str];
+
+    /// Value of `column`, `None` if there is no such column
+    fn field(&self, column: &str) -> Option<Value>;
+}
+
+fn opt_str(s: Option<&StackString>) -> Value {
+    s.map_or(Value::Null, |s| s.as_str().into())
+}
+
+fn opt_date(d: Option<OffsetDateTime>) -> Value {
+    d.and_then(|d| d.format(&Rfc3339).ok())
+        .map_or(Value::Null, Into::into)
+}
+
+impl Listing for Podcast {
+    const COLUMNS: &'static [&'static str] = &[
+        "castid",
+        "castname",
+        "feedurl",
+        "directory",
+        "author",
+        "summary",
+        "image",
+        "explicit",
+        "block",
+        "new_feed_url",
+        "full_episodes_only",
+        "podcast_guid",
+        "locked",
+        "etag",
+        "last_modified",
+        "filename_template",
+        "keep_latest",
+        "max_age_days",
+        "max_total_bytes",
+        "active",
+    ];
+    const DEFAULT_COLUMNS: &'static [&'static str] =
+        &["castid", "castname", "active", "feedurl", "directory"];
+
+    fn field(&self, column: &str) -> Option<Value> {
+        let value = match column {
+            "castid" => self.castid.into(),
+            "castname" => self.castname.as_str().into(),
+            "feedurl" => self.feedurl.as_str().into(),
+            "directory" => opt_str(self.directory.as_ref()),
+            "author" => opt_str(self.author.as_ref()),
+            "summary" => opt_str(self.summary.as_ref()),
+            "image" => opt_str(self.image.as_ref()),
+            "explicit" => self.explicit.into(),
+            "block" => self.block.into(),
+            "new_feed_url" => opt_str(self.new_feed_url.as_ref()),
+            "full_episodes_only" => self.full_episodes_only.into(),
+            "podcast_guid" => opt_str(self.podcast_guid.as_ref()),
+            "locked" => self.locked.into(),
+            "etag" => opt_str(self.etag.as_ref()),
+            "last_modified" => opt_str(self.last_modified.as_ref()),
+            "filename_template" => opt_str(self.filename_template.as_ref()),
+            "keep_latest" => self.keep_latest.into(),
+            "max_age_days" => self.max_age_days.into(),
+            "max_total_bytes" => self.max_total_bytes.into(),
+            "active" => self.active.into(),
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+impl Listing for Episode {
+    const COLUMNS: &'static [&'static str] = &[
+        "castid",
+        "episodeid",
+        "title",
+        "epurl",
+        "enctype",
+        "status",
+        "guid",
+        "checksum",
+        "enclength",
+        "pubdate",
+        "description",
+        "link",
+        "duration",
+        "episode_number",
+        "season_number",
+        "episode_type",
+        "explicit",
+        "image",
+        "author",
+        "summary",
+        "block",
+        "error_message",
+        "attempts",
+        "filename",
+    ];
+    const DEFAULT_COLUMNS: &'static [&'static str] =
+        &["castid", "episodeid", "status", "pubdate", "title"];
+
+    fn field(&self, column: &str) -> Option<Value> {
+        let value = match column {
+            "castid" => self.castid.into(),
+            "episodeid" => self.episodeid.into(),
+            "title" => self.title.as_str().into(),
+            "epurl" => self.epurl.as_str().into(),
+            "enctype" => self.enctype.as_str().into(),
+            "status" => self.status.to_str().into(),
+            "guid" => opt_str(self.guid.as_ref()),
+            "checksum" => opt_str(self.checksum.as_ref()),
+            "enclength" => self.enclength.into(),
+            "pubdate" => opt_date(self.pubdate),
+            "description" => opt_str(self.description.as_ref()),
+            "link" => opt_str(self.link.as_ref()),
+            "duration" => self.duration.into(),
+            "episode_number" => self.episode_number.into(),
+            "season_number" => self.season_number.into(),
+            "episode_type" => opt_str(self.episode_type.as_ref()),
+            "explicit" => self.explicit.into(),
+            "image" => opt_str(self.image.as_ref()),
+            "author" => opt_str(self.author.as_ref()),
+            "summary" => opt_str(self.summary.as_ref()),
+            "block" => self.block.into(),
+            "error_message" => opt_str(self.error_message.as_ref()),
+            "attempts" => self.attempts.into(),
+            "filename" => opt_str(self.filename.as_ref()),
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+/// Null sorts first, numbers numerically and everything else by its text
+fn compare_values(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) => Ordering::Less,
+        (_, Value::Null) => Ordering::Greater,
+        (Value::Number(a), Value::Number(b)) => {
+            let a = a.as_f64().unwrap_or(0.0);
+            let b = b.as_f64().unwrap_or(0.0);
+            a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+        }
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        _ => cell(a).cmp(&cell(b)),
+    }
+}
+
+/// Text of a value in a table or csv, null is empty
+fn cell(value: &Value) -> StackString {
+    match value {
+        Value::Null => "".into(),
+        Value::String(s) => s.as_str().into(),
+        v => format_sstr!("{v}"),
+    }
+}
+
+fn csv_field(s: &str) -> StackString {
+    if s.contains([',', '"', '\n', '\r']) {
+        format_sstr!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.into()
+    }
+}
+
+/// Render `items` in `format`, returns the lines to print.  `columns` picks
+/// the columns to print and their order, `sort` the column to sort by.
+/// # Errors
+/// Return error if a column doesn't exist
+pub fn format_listing<T: Listing>(
+    items: &[T],
+    format: OutputFormat,
+    columns: &[StackString],
+    sort: Option<&str>,
+    reverse: bool,
+) -> Result<Vec<StackString>, Error> {
+    let columns: Vec<&str> = if !columns.is_empty() {
+        columns.iter().map(StackString::as_str).collect()
+    } else if format == OutputFormat::Table {
+        T::DEFAULT_COLUMNS.to_vec()
+    } else {
+        T::COLUMNS.to_vec()
+    };
+    for column in columns.iter().copied().chain(sort) {
+        if !T::COLUMNS.contains(&column) {
+            return Err(format_err!(
+                "Unknown column {column}, expected one of {}",
+                T::COLUMNS.join(", ")
+            ));
+        }
+    }
+    let mut items: Vec<&T> = items.iter().collect();
+    if let Some(sort) = sort {
+        items.sort_by(|a, b| {
+            let a = a.field(sort).unwrap_or(Value::Null);
+            let b = b.field(sort).unwrap_or(Value::Null);
+            compare_values(&a, &b)
+        });
+    }
+    if reverse {
+        items.reverse();
+    }
+    let rows: Vec<Vec<Value>> = items
+        .into_iter()
+        .map(|item| {
+            columns
+                .iter()
+                .map(|c| item.field(c).unwrap_or(Value::Null))
+                .collect()
+        })
+        .collect();
+    let to_object = |row: Vec<Value>| -> Value {
+        let object: Map<String, Value> =
+            columns.iter().map(|c| (*c).to_string()).zip(row).collect();
+        Value::Object(object)
+    };
+    let lines = match format {
+        OutputFormat::Json => {
+            let rows: Vec<_> = rows.into_iter().map(to_object).collect();
+            vec![serde_json::to_string_pretty(&rows)?.into()]
+        }
+        OutputFormat::Jsonl => rows
+            .into_iter()
+            .map(|row| serde_json::to_string(&to_object(row)).map(Into::into))
+            .collect::<Result<_, _>>()?,
+        OutputFormat::Csv => {
+            let header = columns.iter().map(|c| csv_field(c)).collect::<Vec<_>>();
+            let mut lines = vec![header.join(",").into()];
+            for row in rows {
+                let row: Vec<_> = row.iter().map(|v| csv_field(&cell(v))).collect();
+                lines.push(row.join(",").into());
+            }
+            lines
+        }
+        OutputFormat::Table => {
+            let mut table: Vec<Vec<StackString>> =
+                vec![columns.iter().map(|c| (*c).into()).collect()];
+            for row in &rows {
+                table.push(
+                    row.iter()
+                        .map(|v| cell(v).replace('\n', " ").into())
+                        .collect(),
+                );
+            }
+            let widths: Vec<usize> = (0..columns.len())
+                .map(|idx| {
+                    table
+                        .iter()
+                        .map(|row| row[idx].chars().count())
+                        .max()
+                        .unwrap_or(0)
+                })
+                .collect();
+            table
+                .iter()
+                .map(|row| {
+                    let line: Vec<_> = row
+                        .iter()
+                        .zip(&widths)
+                        .map(|(c, w)| format!("{c:w$}"))
+                        .collect();
+                    line.join("  ").trim_end().into()
+                })
+                .collect()
+        }
+    };
+    Ok(lines)
+}
+
+/// Render every column of a single item as a `column  value` line, empty
+/// columns are left out
+#[must_use]
+pub fn format_record<T: Listing>(item: &T) -> Vec<StackString> {
+    let width = T::COLUMNS.iter().map(|c| c.len()).max().unwrap_or(0);
+    T::COLUMNS
+        .iter()
+        .filter_map(|column| {
+            let value = item.field(column)?;
+            if value.is_null() {
+                return None;
+            }
+            let value = cell(&value).replace('\n', " ");
+            Some(format_sstr!("{column:width$}  {value}"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+
+    use crate::{
+        episode::Episode,
+        episode_status::EpisodeStatus,
+        listing::{format_listing, format_record, OutputFormat},
+    };
+
+    fn episodes() -> Vec<Episode> {
+        vec![
+            Episode {
+                castid: 1,
+                episodeid: 2,
+                title: "Second, \"the\" sequel".into(),
+                status: EpisodeStatus::Downloaded,
+                ..Episode::default()
+            },
+            Episode {
+                castid: 1,
+                episodeid: 10,
+                title: "Tenth".into(),
+                ..Episode::default()
+            },
+            Episode {
+                castid: 1,
+                episodeid: 1,
+                title: "First".into(),
+                ..Episode::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn test_format_listing() -> Result<(), Error> {
+        let episodes = episodes();
+        let columns = ["episodeid".into(), "title".into()];
+        let lines = format_listing(
+            &episodes,
+            OutputFormat::Table,
+            &columns,
+            Some("episodeid"),
+            false,
+        )?;
+        assert_eq!(
+            lines,
+            vec![
+                "episodeid  title",
+                "1          First",
+                "2          Second, \"the\" sequel",
+                "10         Tenth",
+            ]
+        );
+        let lines = format_listing(&episodes, OutputFormat::Csv, &columns, None, false)?;
+        assert_eq!(
+            lines,
+            vec![
+                "episodeid,title",
+                "2,\"Second, \"\"the\"\" sequel\"",
+                "10,Tenth",
+                "1,First",
+            ]
+        );
+        let columns = ["episodeid".into(), "status".into()];
+        let lines = format_listing(
+            &episodes,
+            OutputFormat::Jsonl,
+            &columns,
+            Some("title"),
+            true,
+        )?;
+        assert_eq!(
+            lines,
+            vec![
+                r#"{"episodeid":10,"status":"Ready"}"#,
+                r#"{"episodeid":2,"status":"Downloaded"}"#,
+                r#"{"episodeid":1,"status":"Ready"}"#,
+            ]
+        );
+        // objects keep the order of the columns
+        let columns = ["title".into(), "episodeid".into()];
+        let lines = format_listing(&episodes, OutputFormat::Jsonl, &columns, None, false)?;
+        assert_eq!(lines[1], r#"{"title":"Tenth","episodeid":10}"#);
+        let lines = format_listing(&episodes, OutputFormat::Json, &[], None, false)?;
+        let value: serde_json::Value = serde_json::from_str(&lines.join("\n"))?;
+        assert_eq!(value.as_array().map(Vec::len), Some(3));
+        assert_eq!(value[0]["pubdate"], serde_json::Value::Null);
+        let keys: Vec<_> = value[0].as_object().unwrap().keys().cloned().collect();
+        assert_eq!(keys[..3], ["castid", "episodeid", "title"]);
+        assert!(format_listing(
+            &episodes,
+            OutputFormat::Table,
+            &["nope".into()],
+            None,
+            false
+        )
+        .is_err());
+        assert!(format_listing(&episodes, OutputFormat::Table, &[], Some("nope"), false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_record() {
+        let episodes = episodes();
+        let lines = format_record(&episodes[1]);
+        assert_eq!(lines[0], "castid          1");
+        assert_eq!(lines[2], "title           Tenth");
+        assert!(lines.iter().all(|l| !l.starts_with("pubdate")));
+    }
+}
@@ -0,0 +1,132 @@
+use anyhow::Error;
+use std::{fs::File, io::Read, path::Path};
+
+/// File extension for the media types podcasts are published in
+#[must_use]
+pub fn extension_for_content_type(content_type: &str) -> Option<&'static str> {
+    let mime = content_type.split(';').next()?.trim().to_lowercase();
+    match mime.as_str() {
+        "audio/mpeg" | "audio/mp3" | "audio/mpeg3" | "audio/x-mpeg" => Some("mp3"),
+        "audio/mp4" | "audio/x-m4a" | "audio/m4a" => Some("m4a"),
+        "audio/aac" | "audio/x-aac" | "audio/aacp" => Some("aac"),
+        "video/mp4" | "video/x-m4v" => Some("mp4"),
+        "audio/ogg" | "audio/vorbis" | "application/ogg" => Some("ogg"),
+        "audio/opus" => Some("opus"),
+        "audio/wav" | "audio/x-wav" | "audio/wave" => Some("wav"),
+        "audio/flac" | "audio/x-flac" => Some("flac"),
+        "video/quicktime" => Some("mov"),
+        _ => None,
+    }
+}
+
+/// Guess the file extension from the magic bytes at the start of a file
+#[must_use]
+pub fn sniff_extension(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"ID3") {
+        Some("mp3")
+    } else if bytes.len() > 1 && bytes[0] == 0xff && bytes[1] & 0xe0 == 0xe0 {
+        // mpeg audio frames have a non zero layer, adts frames use layer 0
+        if bytes[1] & 0x06 == 0 {
+            Some("aac")
+        } else {
+            Some("mp3")
+        }
+    } else if bytes.len() >= 12 && &bytes[4..8] == b"ftyp" {
+        match &bytes[8..12] {
+            b"M4A " | b"M4B " => Some("m4a"),
+            _ => Some("mp4"),
+        }
+    } else if bytes.starts_with(b"OggS") {
+        if bytes.len() >= 36 && &bytes[28..36] == b"OpusHead" {
+            Some("opus")
+        } else {
+            Some("ogg")
+        }
+    } else if bytes.len() >= 12 && bytes.starts_with(b"RIFF") && &bytes[8..12] == b"WAVE" {
+        Some("wav")
+    } else if bytes.starts_with(b"fLaC") {
+        Some("flac")
+    } else {
+        None
+    }
+}
+
+/// Extension of a downloaded file, from the response `Content-Type` if it is
+/// a known media type and from the contents of the file otherwise
+/// # Errors
+/// Return error if reading the file fails
+pub fn detect_extension(
+    path: &Path,
+    content_type: Option<&str>,
+) -> Result<Option<&'static str>, Error> {
+    if let Some(ext) = content_type.and_then(extension_for_content_type) {
+        return Ok(Some(ext));
+    }
+    let mut buf = Vec::with_capacity(64);
+    File::open(path)?.take(64).read_to_end(&mut buf)?;
+    Ok(sniff_extension(&buf))
+}
+
+/// Whether two extensions name the same container format
+#[must_use]
+pub fn same_format(a: &str, b: &str) -> bool {
+    fn family(ext: &str) -> String {
+        let ext = ext.to_lowercase();
+        match ext.as_str() {
+            "m4a" | "m4b" | "mp4" | "m4v" => "mp4".into(),
+            "ogg" | "oga" | "opus" => "ogg".into(),
+            "wave" => "wav".into(),
+            _ => ext,
+        }
+    }
+    family(a) == family(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::media_type::{extension_for_content_type, same_format, sniff_extension};
+
+    #[test]
+    fn test_extension_for_content_type() {
+        assert_eq!(extension_for_content_type("audio/mpeg"), Some("mp3"));
+        assert_eq!(
+            extension_for_content_type("Audio/MP4; codecs=mp4a"),
+            Some("m4a")
+        );
+        assert_eq!(extension_for_content_type("video/mp4"), Some("mp4"));
+        assert_eq!(extension_for_content_type("audio/ogg"), Some("ogg"));
+        assert_eq!(extension_for_content_type("audio/opus"), Some("opus"));
+        assert_eq!(extension_for_content_type("application/octet-stream"), None);
+        assert_eq!(extension_for_content_type(""), None);
+    }
+
+    #[test]
+    fn test_sniff_extension() {
+        assert_eq!(sniff_extension(b"ID3\x04\x00"), Some("mp3"));
+        assert_eq!(sniff_extension(&[0xff, 0xfb, 0x90, 0x64]), Some("mp3"));
+        assert_eq!(sniff_extension(&[0xff, 0xf1, 0x50, 0x80]), Some("aac"));
+        assert_eq!(
+            sniff_extension(b"\x00\x00\x00\x20ftypM4A \x00"),
+            Some("m4a")
+        );
+        assert_eq!(
+            sniff_extension(b"\x00\x00\x00\x20ftypisom\x00"),
+            Some("mp4")
+        );
+        let mut opus = b"OggS".to_vec();
+        opus.resize(28, 0);
+        opus.extend_from_slice(b"OpusHead");
+        assert_eq!(sniff_extension(&opus), Some("opus"));
+        assert_eq!(sniff_extension(b"OggS\x00\x02"), Some("ogg"));
+        assert_eq!(sniff_extension(b"<html>"), None);
+        assert_eq!(sniff_extension(b""), None);
+    }
+
+    #[test]
+    fn test_same_format() {
+        assert!(same_format("m4a", "MP4"));
+        assert!(same_format("opus", "ogg"));
+        assert!(same_format("mp3", "mp3"));
+        assert!(!same_format("mp3", "m4a"));
+    }
+}
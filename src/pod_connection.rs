@@ -1,16 +1,102 @@
 use anyhow::{format_err, Error};
 use futures::StreamExt;
-use reqwest::{Client, Url};
-use roxmltree::{Document, NodeType};
-use stack_string::StackString;
-use std::{collections::HashSet, path::Path};
-use tokio::{fs::File, io::AsyncWriteExt};
+use log::debug;
+use reqwest::{
+    header::{
+        HeaderMap, HeaderValue, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE,
+        IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED, LOCATION, RANGE,
+    },
+    redirect::Policy,
+    Client, StatusCode, Url,
+};
+use stack_string::{format_sstr, StackString};
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::{Mutex, OwnedSemaphorePermit, Semaphore},
+};
 
-use crate::{episode::Episode, exponential_retry::ExponentialRetry, podcast::Podcast};
+use crate::{
+    config::Config,
+    episode::Episode,
+    episode_index::{EpisodeIndex, EpisodeMatch},
+    exponential_retry::{ExponentialRetry, RetryPolicy},
+    feed::{Feed, FeedItem},
+    podcast::Podcast,
+};
+
+const MAX_REDIRECTS: usize = 10;
+
+pub const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 8;
+pub const DEFAULT_MAX_REQUESTS_PER_HOST: usize = 2;
+
+/// Limits the number of requests in flight, overall and per host
+#[derive(Clone)]
+pub struct HostLimiter {
+    global: Arc<Semaphore>,
+    per_host: Arc<Mutex<HashMap<StackString, Arc<Semaphore>>>>,
+    max_per_host: usize,
+}
+
+/// Held for the duration of a request
+pub struct HostPermit {
+    _host: OwnedSemaphorePermit,
+    _global: OwnedSemaphorePermit,
+}
+
+impl Default for HostLimiter {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_MAX_CONCURRENT_REQUESTS,
+            DEFAULT_MAX_REQUESTS_PER_HOST,
+        )
+    }
+}
+
+impl HostLimiter {
+    #[must_use]
+    pub fn new(max_total: usize, max_per_host: usize) -> Self {
+        Self {
+            global: Arc::new(Semaphore::new(max_total.max(1))),
+            per_host: Arc::new(Mutex::new(HashMap::new())),
+            max_per_host: max_per_host.max(1),
+        }
+    }
+
+    /// Wait for a free slot for the host of `url`.  The host slot is taken
+    /// first so that requests queued behind a busy host don't hold up
+    /// requests to other hosts.
+    /// # Errors
+    /// Return error if a semaphore was closed
+    pub async fn acquire(&self, url: &Url) -> Result<HostPermit, Error> {
+        let host = self
+            .per_host
+            .lock()
+            .await
+            .entry(url.host_str().unwrap_or("").into())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.max_per_host)))
+            .clone();
+        let host = host.acquire_owned().await?;
+        let global = self.global.clone().acquire_owned().await?;
+        Ok(HostPermit {
+            _host: host,
+            _global: global,
+        })
+    }
+}
 
 #[derive(Clone)]
 pub struct PodConnection {
     client: Client,
+    feed_client: Client,
+    retry_policy: RetryPolicy,
+    limiter: HostLimiter,
 }
 
 impl Default for PodConnection {
@@ -19,151 +105,791 @@ impl Default for PodConnection {
     }
 }
 
+fn is_permanent_redirect(status: StatusCode) -> bool {
+    status == StatusCode::MOVED_PERMANENTLY || status == StatusCode::PERMANENT_REDIRECT
+}
+
+/// The redirects seen while fetching a feed.  Temporary redirects are
+/// followed by the client, permanent ones are followed one at a time so
+/// that the new location can be saved.  Only a chain made up entirely of
+/// permanent redirects moves the feed.
+#[derive(Debug)]
+struct FeedRedirects {
+    redirects: usize,
+    permanent: bool,
+    moved: Option<Url>,
+}
+
+impl Default for FeedRedirects {
+    fn default() -> Self {
+        Self {
+            redirects: 0,
+            permanent: true,
+            moved: None,
+        }
+    }
+}
+
+impl FeedRedirects {
+    /// Record the response to a request for `requested` that ended up at
+    /// `url`, returns the url to request next if it is a permanent redirect
+    fn follow(
+        &mut self,
+        requested: &Url,
+        url: &Url,
+        status: StatusCode,
+        headers: &HeaderMap,
+    ) -> Result<Option<Url>, Error> {
+        if url != requested {
+            self.permanent = false;
+        }
+        if !is_permanent_redirect(status) {
+            return Ok(None);
+        }
+        self.redirects += 1;
+        if self.redirects > MAX_REDIRECTS {
+            return Err(format_err!("Too many redirects for {requested}"));
+        }
+        let location = headers
+            .get(LOCATION)
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| format_err!("Redirect without location {url}"))?;
+        let next = url.join(location)?;
+        if self.permanent {
+            self.moved = Some(next.clone());
+        }
+        Ok(Some(next))
+    }
+}
+
 impl PodConnection {
     #[must_use]
     pub fn new() -> Self {
+        // permanent redirects of a feed are handled in `get_feed` so that the
+        // new location can be saved
+        let policy = Policy::custom(|attempt| {
+            if is_permanent_redirect(attempt.status()) {
+                attempt.stop()
+            } else if attempt.previous().len() > MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else {
+                attempt.follow()
+            }
+        });
         Self {
             client: Client::new(),
+            feed_client: Client::builder()
+                .redirect(policy)
+                .build()
+                .expect("Failed to build feed client"),
+            retry_policy: RetryPolicy::default(),
+            limiter: HostLimiter::default(),
+        }
+    }
+
+    #[must_use]
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            retry_policy: config.retry_policy(),
+            limiter: HostLimiter::new(
+                config.max_concurrent_requests(),
+                config.max_requests_per_host(),
+            ),
+            ..Self::new()
         }
     }
 
     fn get_current_episode(
         podcast: &Podcast,
-        title: Option<&str>,
-        epurl: Option<&str>,
-        enctype: Option<&str>,
-        filter_urls: &HashSet<Episode>,
+        item: &FeedItem,
+        filter_urls: &EpisodeIndex,
         latest_epid: i32,
-    ) -> Option<Episode> {
-        if let Some(epurl) = epurl.as_ref() {
+    ) -> Option<(Episode, Option<EpisodeMatch>)> {
+        let title = item.title.as_ref().map(StackString::as_str);
+        if let Some(enclosure) = item.enclosure.as_ref() {
             let ep = Episode {
                 title: title.map_or_else(|| "Unknown".into(), Into::into),
                 castid: podcast.castid,
                 episodeid: latest_epid,
-                epurl: (*epurl).into(),
-                enctype: enctype.map_or_else(|| "".into(), Into::into),
+                epurl: enclosure.url.clone(),
+                enctype: enclosure.enctype.clone().unwrap_or_else(|| "".into()),
+                enclength: enclosure.length,
+                pubdate: item.pubdate,
+                description: item.description.clone(),
+                link: item.link.clone(),
+                duration: item.itunes.duration,
+                episode_number: item.itunes.episode,
+                season_number: item.itunes.season,
+                episode_type: item.itunes.episode_type.clone(),
+                explicit: item.itunes.explicit,
+                image: item.itunes.image.clone(),
+                author: item.itunes.author.clone(),
+                summary: item.itunes.summary.clone(),
+                block: item.itunes.block,
+                guid: item.guid.clone(),
                 ..Episode::default()
             };
 
-            let url_exists = filter_urls.contains(ep.title.as_str());
+            let existing = filter_urls.find(
+                ep.guid.as_ref().map(StackString::as_str),
+                &ep.epurl,
+                &ep.title,
+            );
 
-            if !url_exists {
-                return Some(ep);
-            } else if let Some(epi) = filter_urls.get(ep.title.as_str()) {
+            if let Some((epi, reason)) = existing {
                 if let Some(title_) = title {
                     if title_ == "Wedgie diplomacy: Bugle 4083" {
                         return None;
                     }
+                    let mut p = epi.clone();
                     if epi.title != title_ {
-                        let mut p = epi.clone();
                         p.title = title_.into();
-                        return Some(p);
-                    } else if let Some(epguid) = epi.epguid.as_ref() {
-                        if epguid.len() != 32 {
-                            return Some(epi.clone());
-                        }
+                    }
+                    // episodes recorded before guids were stored get theirs
+                    // filled in, so they are matched by guid from now on
+                    if epi.guid.is_none() {
+                        p.guid.clone_from(&ep.guid);
+                    }
+                    // a guid match with a new url is a re-hosted episode
+                    if reason == EpisodeMatch::Guid {
+                        p.epurl.clone_from(&ep.epurl);
+                    }
+                    // episodes recorded before the itunes metadata was stored
+                    // get it filled in
+                    let metadata_changed = p.update_metadata(&ep);
+                    // failed downloads wait until they are re-queued, deleted ones for good
+                    let needs_checksum = epi.checksum.is_none() && !epi.status.skips_download();
+                    if p.title != epi.title
+                        || p.guid != epi.guid
+                        || p.epurl != epi.epurl
+                        || metadata_changed
+                        || needs_checksum
+                    {
+                        return Some((p, Some(reason)));
                     }
                 }
+            } else {
+                return Some((ep, None));
             }
         }
         None
     }
 
+    /// Fetch and parse the feed, sending the cached `ETag` and
+    /// `Last-Modified` of the podcast so unchanged feeds aren't downloaded
+    /// again.  Returns `None` if the server reports the feed as unchanged,
+    /// otherwise the cache headers of the podcast are updated.  If the feed
+    /// has permanently moved `feedurl` is set to the new location.
     /// # Errors
     /// Return error if api call fails
-    pub async fn parse_feed(
-        &self,
+    pub async fn get_feed(&self, podcast: &mut Podcast) -> Result<Option<Feed>, Error> {
+        let mut url: Url = podcast.feedurl.parse()?;
+        let mut headers = HeaderMap::new();
+        if let Some(etag) = podcast.etag.as_ref() {
+            headers.insert(IF_NONE_MATCH, etag.parse()?);
+        }
+        if let Some(last_modified) = podcast.last_modified.as_ref() {
+            headers.insert(IF_MODIFIED_SINCE, last_modified.parse()?);
+        }
+        let mut _permit = self.limiter.acquire(&url).await?;
+        let mut redirects = FeedRedirects::default();
+        let resp = loop {
+            let resp = self.client_get(&self.feed_client, &url, &headers).await?;
+            match redirects.follow(&url, resp.url(), resp.status(), resp.headers())? {
+                Some(next) => {
+                    // a redirect to another host waits for a slot of that
+                    // host, the slot of the old one is given up first
+                    if next.host_str() != url.host_str() {
+                        drop(_permit);
+                        _permit = self.limiter.acquire(&next).await?;
+                    }
+                    url = next;
+                }
+                None => break resp,
+            }
+        };
+        if let Some(moved) = redirects.moved {
+            podcast.feedurl = moved.as_str().into();
+        }
+        if resp.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        let resp = resp.error_for_status()?;
+        let header_value = |name| {
+            resp.headers()
+                .get(name)
+                .and_then(|v: &HeaderValue| v.to_str().ok())
+                .map(Into::into)
+        };
+        let etag = header_value(ETAG);
+        let last_modified = header_value(LAST_MODIFIED);
+        let text = resp.text().await?;
+        let feed = Feed::parse(&text)?;
+        podcast.etag = etag;
+        podcast.last_modified = last_modified;
+        Ok(Some(feed))
+    }
+
+    /// Returns the new episodes of the feed, and the known episodes whose
+    /// metadata needs updating along with the reason they matched
+    #[must_use]
+    pub fn get_episodes(
         podcast: &Podcast,
-        filter_urls: &HashSet<Episode>,
+        feed: &Feed,
+        filter_urls: &EpisodeIndex,
         mut latest_epid: i32,
-    ) -> Result<Vec<Episode>, Error> {
-        let url = podcast.feedurl.parse()?;
-        let text = self.get(&url).await?.text().await?;
-        let doc = Document::parse(&text).map_err(|e| format_err!("{e:?}"))?;
-
+    ) -> Vec<(Episode, Option<EpisodeMatch>)> {
         let mut episodes = Vec::new();
-        let mut title: Option<StackString> = None;
-        let mut epurl: Option<StackString> = None;
-        let mut enctype: Option<StackString> = None;
-
-        for d in doc.root().descendants() {
-            if d.node_type() == NodeType::Element && d.tag_name().name() == "title" {
-                if epurl.is_some() {
-                    if let Some(epi) = Self::get_current_episode(
-                        podcast,
-                        title.as_ref().map(StackString::as_str),
-                        epurl.as_ref().map(StackString::as_str),
-                        enctype.as_ref().map(StackString::as_str),
-                        filter_urls,
-                        latest_epid,
-                    ) {
-                        episodes.push(epi);
-                    }
-                    title = None;
-                    epurl = None;
-                    enctype = None;
-                    latest_epid += 1;
-                }
-                if let Some(t) = d.text() {
-                    title = Some(t.into());
-                }
+
+        for item in feed.items.iter().filter(|item| item.enclosure.is_some()) {
+            if podcast.full_episodes_only && !item.itunes.is_full_episode() {
+                continue;
             }
-            for a in d.attributes() {
-                match a.name() {
-                    "url" => epurl = Some(a.value().into()),
-                    "type" => enctype = Some(a.value().into()),
-                    _ => (),
-                }
+            if let Some(epi) = Self::get_current_episode(podcast, item, filter_urls, latest_epid) {
+                episodes.push(epi);
             }
+            latest_epid += 1;
         }
 
-        if let Some(epi) = Self::get_current_episode(
-            podcast,
-            title.as_ref().map(StackString::as_str),
-            epurl.as_ref().map(StackString::as_str),
-            enctype.as_ref().map(StackString::as_str),
+        episodes
+    }
+
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn parse_feed(
+        &self,
+        podcast: &Podcast,
+        filter_urls: &EpisodeIndex,
+        latest_epid: i32,
+    ) -> Result<Vec<(Episode, Option<EpisodeMatch>)>, Error> {
+        let mut podcast = podcast.clone();
+        podcast.etag = None;
+        podcast.last_modified = None;
+        let feed = self
+            .get_feed(&mut podcast)
+            .await?
+            .ok_or_else(|| format_err!("No feed returned"))?;
+        Ok(Self::get_episodes(
+            &podcast,
+            &feed,
             filter_urls,
             latest_epid,
-        ) {
-            episodes.push(epi);
-        }
-
-        Ok(episodes)
+        ))
     }
 
+    /// Download `url` to `outpath`, returning the `Content-Type` of the
+    /// response.  The data is written to `<outpath>.part` in the same
+    /// directory, an interrupted download left behind by a previous run is
+    /// resumed with a `Range` request if the server supports it and
+    /// restarted from scratch otherwise.  The `ETag` or `Last-Modified` of
+    /// the response is kept in `<outpath>.part.validator` and sent as
+    /// `If-Range`, so a file that changed on the server in the meantime is
+    /// downloaded again in full rather than spliced onto the old part.  Only
+    /// once the whole body has been received and synced to disk is the file
+    /// renamed to `outpath`, so a file at the final path is always complete.
+    /// A download more than 1% off `expected_length` is deleted rather than
+    /// renamed, the next attempt downloads it again from scratch.
     /// # Errors
-    /// Return error if api call fails
-    pub async fn dump_to_file(&self, url: &Url, outpath: &Path) -> Result<(), Error> {
-        if outpath.exists() {
-            Err(format_err!("File exists"))
+    /// Return error if api call fails, or a [`SizeMismatch`] if the download
+    /// doesn't match the response `Content-Length` or `expected_length`
+    pub async fn dump_to_file(
+        &self,
+        url: &Url,
+        outpath: &Path,
+        expected_length: Option<u64>,
+    ) -> Result<Download, Error> {
+        let _permit = self.limiter.acquire(url).await?;
+        let partpath = partial_path(outpath);
+        let validatorpath = validator_path(outpath);
+        let offset = match fs::metadata(&partpath).await {
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        };
+        // without a validator there is no telling whether the part still
+        // belongs to the file on the server, so it is only resumed with one
+        let validator = if offset > 0 {
+            fs::read_to_string(&validatorpath)
+                .await
+                .ok()
+                .filter(|v| !v.trim().is_empty())
+        } else {
+            None
+        };
+        let mut resp = if let Some(validator) = validator.as_ref() {
+            let mut headers = HeaderMap::new();
+            headers.insert(RANGE, format_sstr!("bytes={offset}-").parse()?);
+            headers.insert(IF_RANGE, validator.trim().parse()?);
+            self.get_with_headers(url, &headers).await?
+        } else {
+            self.get(url).await?
+        };
+        if resp.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            debug!("restart download {url}");
+            resp = self.get(url).await?;
+        }
+        let mut resp = resp.error_for_status()?;
+        let resumed = validator.is_some()
+            && resp.status() == StatusCode::PARTIAL_CONTENT
+            && content_range_start(resp.headers()) == Some(offset);
+        if !resumed && resp.status() == StatusCode::PARTIAL_CONTENT {
+            // a range that doesn't continue the part, start over without one
+            debug!("restart download {url}");
+            resp = self.get(url).await?.error_for_status()?;
+            if resp.status() == StatusCode::PARTIAL_CONTENT {
+                return Err(format_err!("Unexpected partial content for {url}"));
+            }
+        }
+        let (mut f, mut written) = if resumed {
+            debug!("resume download {url} at {offset}");
+            (
+                OpenOptions::new().append(true).open(&partpath).await?,
+                offset,
+            )
         } else {
-            let mut f = File::create(outpath).await?;
-            let mut byte_stream = self.get(url).await?.bytes_stream();
-            while let Some(item) = byte_stream.next().await {
-                f.write_all(&item?).await?;
+            match range_validator(resp.headers()) {
+                Some(validator) => fs::write(&validatorpath, validator.as_bytes()).await?,
+                None => remove_if_exists(&validatorpath).await?,
+            }
+            (File::create(&partpath).await?, 0)
+        };
+        let expected = resp.content_length().map(|l| written + l);
+        let content_type = resp
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(Into::into);
+        let mut byte_stream = resp.bytes_stream();
+        while let Some(item) = byte_stream.next().await {
+            let item = item?;
+            f.write_all(&item).await?;
+            written += item.len() as u64;
+        }
+        f.sync_all().await?;
+        drop(f);
+        if let Err(mismatch) = check_size(written, expected, expected_length) {
+            // a truncated body is kept around so the next attempt can resume
+            if !matches!(mismatch, SizeMismatch::Truncated { .. }) {
+                fs::remove_file(&partpath).await?;
+                remove_if_exists(&validatorpath).await?;
             }
-            Ok(())
+            return Err(mismatch.into());
         }
+        fs::rename(&partpath, outpath).await?;
+        remove_if_exists(&validatorpath).await?;
+        Ok(Download { content_type })
     }
 }
 
+/// A completed download
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Download {
+    /// `Content-Type` of the response
+    pub content_type: Option<StackString>,
+}
+
+/// The size of a download doesn't match the size announced by the server or
+/// the feed
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeMismatch {
+    #[error("empty download")]
+    Empty,
+    #[error("incomplete download, got {written} of {expected} bytes")]
+    Truncated { written: u64, expected: u64 },
+    #[error("download exceeds Content-Length, got {written} of {expected} bytes")]
+    Oversized { written: u64, expected: u64 },
+    #[error("download doesn't match enclosure length, got {written} of {expected} bytes")]
+    EnclosureLength { written: u64, expected: u64 },
+}
+
+/// Compare the number of bytes written against the response `Content-Length`
+/// and the enclosure `length` of the feed.  An empty download, one not
+/// matching the `Content-Length` or one more than 1% off the enclosure
+/// length is an error.
+fn check_size(
+    written: u64,
+    content_length: Option<u64>,
+    enclosure_length: Option<u64>,
+) -> Result<(), SizeMismatch> {
+    if written == 0 {
+        return Err(SizeMismatch::Empty);
+    }
+    if let Some(expected) = content_length {
+        if written < expected {
+            return Err(SizeMismatch::Truncated { written, expected });
+        } else if written > expected {
+            return Err(SizeMismatch::Oversized { written, expected });
+        }
+    }
+    if let Some(expected) = enclosure_length {
+        if written.abs_diff(expected) > expected / 100 {
+            return Err(SizeMismatch::EnclosureLength { written, expected });
+        }
+    }
+    Ok(())
+}
+
+/// Path of the partial download of `outpath`
+#[must_use]
+pub fn partial_path(outpath: &Path) -> PathBuf {
+    let mut partpath = outpath.as_os_str().to_os_string();
+    partpath.push(".part");
+    partpath.into()
+}
+
+/// Path of the validator of the partial download of `outpath`
+#[must_use]
+pub fn validator_path(outpath: &Path) -> PathBuf {
+    let mut validatorpath = partial_path(outpath).into_os_string();
+    validatorpath.push(".validator");
+    validatorpath.into()
+}
+
+/// Validator to send as `If-Range` when resuming a download of this
+/// response: a strong `ETag`, or the `Last-Modified` date
+fn range_validator(headers: &HeaderMap) -> Option<StackString> {
+    let etag = headers
+        .get(ETAG)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.starts_with("W/"));
+    etag.or_else(|| headers.get(LAST_MODIFIED).and_then(|v| v.to_str().ok()))
+        .map(Into::into)
+}
+
+async fn remove_if_exists(path: &Path) -> Result<(), Error> {
+    match fs::remove_file(path).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Whether a failed request is pointless to repeat, the server reports the
+/// resource as missing or gone for good
+#[must_use]
+pub fn is_permanent_failure(error: &Error) -> bool {
+    error
+        .downcast_ref::<reqwest::Error>()
+        .and_then(reqwest::Error::status)
+        .is_some_and(|status| status == StatusCode::NOT_FOUND || status == StatusCode::GONE)
+}
+
+/// Start offset of a `Content-Range: bytes <start>-<end>/<total>` header
+fn content_range_start(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .trim()
+        .strip_prefix("bytes ")?
+        .split('-')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
 impl ExponentialRetry for PodConnection {
     fn get_client(&self) -> &Client {
         &self.client
     }
+
+    fn get_retry_policy(&self) -> RetryPolicy {
+        self.retry_policy
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use anyhow::Error;
-    use reqwest::Url;
-    use std::collections::HashSet;
+    use reqwest::{
+        header::{HeaderMap, CONTENT_RANGE, ETAG, LAST_MODIFIED, LOCATION},
+        StatusCode, Url,
+    };
+    use std::{path::Path, time::Duration};
+    use tokio::time::timeout;
 
     use crate::{
-        config::Config, episode::Episode, exponential_retry::ExponentialRetry, pgpool::PgPool,
-        pod_connection::PodConnection, podcast::Podcast,
+        config::Config,
+        episode::Episode,
+        episode_index::{EpisodeIndex, EpisodeMatch},
+        episode_status::EpisodeStatus,
+        exponential_retry::ExponentialRetry,
+        feed::Feed,
+        pgpool::PgPool,
+        pod_connection::{
+            check_size, content_range_start, is_permanent_redirect, partial_path, range_validator,
+            validator_path, FeedRedirects, HostLimiter, PodConnection, SizeMismatch,
+        },
+        podcast::Podcast,
     };
 
+    #[tokio::test]
+    async fn test_host_limiter() -> Result<(), Error> {
+        let limiter = HostLimiter::new(2, 1);
+        let a: Url = "https://a.example/1.mp3".parse()?;
+        let b: Url = "https://b.example/1.mp3".parse()?;
+        let c: Url = "https://c.example/1.mp3".parse()?;
+        let wait = Duration::from_millis(10);
+
+        let permit_a = limiter.acquire(&a).await?;
+        assert!(timeout(wait, limiter.acquire(&a)).await.is_err());
+        let _permit_b = limiter.acquire(&b).await?;
+        assert!(timeout(wait, limiter.acquire(&c)).await.is_err());
+        drop(permit_a);
+        assert!(timeout(wait, limiter.acquire(&c)).await.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_partial_path() {
+        assert_eq!(
+            partial_path(Path::new("/tmp/show/episode.mp3")),
+            Path::new("/tmp/show/episode.mp3.part")
+        );
+        assert_eq!(
+            validator_path(Path::new("/tmp/show/episode.mp3")),
+            Path::new("/tmp/show/episode.mp3.part.validator")
+        );
+    }
+
+    #[test]
+    fn test_range_validator() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(range_validator(&headers), None);
+        headers.insert(
+            LAST_MODIFIED,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(
+            range_validator(&headers).as_deref(),
+            Some("Wed, 21 Oct 2015 07:28:00 GMT")
+        );
+        // weak etags can't be used with If-Range
+        headers.insert(ETAG, "W/\"abc\"".parse().unwrap());
+        assert_eq!(
+            range_validator(&headers).as_deref(),
+            Some("Wed, 21 Oct 2015 07:28:00 GMT")
+        );
+        headers.insert(ETAG, "\"abc\"".parse().unwrap());
+        assert_eq!(range_validator(&headers).as_deref(), Some("\"abc\""));
+    }
+
+    #[test]
+    fn test_check_size() {
+        assert_eq!(check_size(10, Some(10), Some(10)), Ok(()));
+        assert_eq!(check_size(10, None, None), Ok(()));
+        assert_eq!(check_size(0, None, None), Err(SizeMismatch::Empty));
+        assert_eq!(
+            check_size(5, Some(10), Some(10)),
+            Err(SizeMismatch::Truncated {
+                written: 5,
+                expected: 10
+            })
+        );
+        assert_eq!(
+            check_size(12, Some(10), None),
+            Err(SizeMismatch::Oversized {
+                written: 12,
+                expected: 10
+            })
+        );
+        assert_eq!(
+            check_size(10, Some(10), Some(11)),
+            Err(SizeMismatch::EnclosureLength {
+                written: 10,
+                expected: 11
+            })
+        );
+        assert_eq!(check_size(1000, None, Some(1010)), Ok(()));
+        assert_eq!(
+            check_size(1000, None, Some(1011)),
+            Err(SizeMismatch::EnclosureLength {
+                written: 1000,
+                expected: 1011
+            })
+        );
+    }
+
+    #[test]
+    fn test_feed_redirects() -> Result<(), Error> {
+        let feed: Url = "https://example.com/feed.xml".parse()?;
+        let mut headers = HeaderMap::new();
+        headers.insert(LOCATION, "/new/feed.xml".parse()?);
+        let new_feed: Url = "https://example.com/new/feed.xml".parse()?;
+
+        // 301 and 308 move the feed
+        for status in [
+            StatusCode::MOVED_PERMANENTLY,
+            StatusCode::PERMANENT_REDIRECT,
+        ] {
+            let mut redirects = FeedRedirects::default();
+            let next = redirects.follow(&feed, &feed, status, &headers)?;
+            assert_eq!(next.as_ref(), Some(&new_feed));
+            let next = redirects.follow(&new_feed, &new_feed, StatusCode::OK, &headers)?;
+            assert_eq!(next, None);
+            assert_eq!(redirects.moved.as_ref(), Some(&new_feed));
+        }
+
+        // 302 and 307 are followed by the client and don't move the feed
+        assert!(!is_permanent_redirect(StatusCode::FOUND));
+        assert!(!is_permanent_redirect(StatusCode::TEMPORARY_REDIRECT));
+        let temporary: Url = "https://cdn.example.com/feed.xml".parse()?;
+        let mut redirects = FeedRedirects::default();
+        let next = redirects.follow(&feed, &temporary, StatusCode::OK, &headers)?;
+        assert_eq!(next, None);
+        assert_eq!(redirects.moved, None);
+
+        // nor does a permanent redirect behind a temporary one
+        let mut redirects = FeedRedirects::default();
+        let next = redirects.follow(&feed, &temporary, StatusCode::MOVED_PERMANENTLY, &headers)?;
+        assert_eq!(
+            next.map(String::from),
+            Some("https://cdn.example.com/new/feed.xml".into())
+        );
+        assert_eq!(redirects.moved, None);
+
+        let mut redirects = FeedRedirects::default();
+        assert!(redirects
+            .follow(
+                &feed,
+                &feed,
+                StatusCode::MOVED_PERMANENTLY,
+                &HeaderMap::new()
+            )
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_content_range_start() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(content_range_start(&headers), None);
+        headers.insert(CONTENT_RANGE, "bytes 1024-2047/2048".parse().unwrap());
+        assert_eq!(content_range_start(&headers), Some(1024));
+        headers.insert(CONTENT_RANGE, "bytes */2048".parse().unwrap());
+        assert_eq!(content_range_start(&headers), None);
+    }
+
+    #[test]
+    fn test_get_episodes_full_episodes_only() -> Result<(), Error> {
+        let feed = Feed::parse(include_str!("../tests/data/itunes_feed.xml"))?;
+        let mut pod = Podcast {
+            castid: 1,
+            ..Podcast::default()
+        };
+        let index = EpisodeIndex::default();
+        let episodes = PodConnection::get_episodes(&pod, &feed, &index, 10);
+        assert_eq!(episodes.len(), 3);
+        let (epi, _) = &episodes[0];
+        assert_eq!(epi.duration, Some(3723));
+        assert_eq!(epi.season_number, Some(2));
+        assert_eq!(epi.episode_number, Some(5));
+
+        pod.full_episodes_only = true;
+        let episodes = PodConnection::get_episodes(&pod, &feed, &index, 10);
+        let titles: Vec<_> = episodes.iter().map(|(e, _)| e.title.as_str()).collect();
+        assert_eq!(titles, vec!["S2E5", "Old episode"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_episodes_matches_guid() -> Result<(), Error> {
+        let feed = Feed::parse(include_str!("../tests/data/itunes_feed.xml"))?;
+        let pod = Podcast {
+            castid: 1,
+            ..Podcast::default()
+        };
+        let checksum = Some("0123456789abcdef0123456789abcdef".into());
+        let renamed = Episode {
+            castid: 1,
+            episodeid: 3,
+            title: "Season 2, Episode 5".into(),
+            epurl: "https://example.net/s2e5.mp3".into(),
+            guid: Some("s2e5".into()),
+            checksum: checksum.clone(),
+            status: EpisodeStatus::Downloaded,
+            ..Episode::default()
+        };
+        let rehosted = Episode {
+            castid: 1,
+            episodeid: 4,
+            title: "Old episode".into(),
+            epurl: "https://old-host.example.net/old.mp3".into(),
+            guid: Some("old".into()),
+            checksum: checksum.clone(),
+            status: EpisodeStatus::Downloaded,
+            ..Episode::default()
+        };
+        let unchanged = Episode {
+            castid: 1,
+            episodeid: 5,
+            title: "Season 2 Trailer".into(),
+            epurl: "https://example.net/s2-trailer.mp3".into(),
+            checksum,
+            status: EpisodeStatus::Downloaded,
+            ..Episode::default()
+        };
+        let index: EpisodeIndex = vec![renamed, rehosted, unchanged].into_iter().collect();
+        let episodes = PodConnection::get_episodes(&pod, &feed, &index, 10);
+        assert_eq!(episodes.len(), 3);
+
+        let (epi, reason) = &episodes[0];
+        assert_eq!(epi.episodeid, 3);
+        assert_eq!(&epi.title, "S2E5");
+        assert_eq!(epi.status, EpisodeStatus::Downloaded);
+        assert_eq!(*reason, Some(EpisodeMatch::Guid));
+        // metadata missing from the database is filled in from the feed
+        assert_eq!(epi.duration, Some(3723));
+        assert_eq!(epi.season_number, Some(2));
+        assert_eq!(epi.episode_number, Some(5));
+
+        // the trailer gains the guid it was missing
+        let (epi, reason) = &episodes[1];
+        assert_eq!(epi.episodeid, 5);
+        assert_eq!(epi.guid.as_deref(), Some("s2-trailer"));
+        assert_eq!(*reason, Some(EpisodeMatch::Url));
+
+        let (epi, reason) = &episodes[2];
+        assert_eq!(epi.episodeid, 4);
+        assert_eq!(&epi.epurl, "https://example.net/old.mp3");
+        assert_eq!(*reason, Some(EpisodeMatch::Guid));
+
+        let index: EpisodeIndex = episodes.into_iter().map(|(e, _)| e).collect();
+        assert!(PodConnection::get_episodes(&pod, &feed, &index, 10).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_episodes_backfills_legacy_guid() -> Result<(), Error> {
+        let feed = Feed::parse(include_str!("../tests/data/itunes_feed.xml"))?;
+        let pod = Podcast {
+            castid: 1,
+            ..Podcast::default()
+        };
+        // recorded before guids were stored, the enclosure url has changed
+        // since
+        let legacy = Episode {
+            castid: 1,
+            episodeid: 4,
+            title: "Old Episode".into(),
+            epurl: "https://tracking.example/example.net/old.mp3".into(),
+            checksum: Some("0123456789abcdef0123456789abcdef".into()),
+            status: EpisodeStatus::Downloaded,
+            ..Episode::default()
+        };
+        let index: EpisodeIndex = vec![legacy].into_iter().collect();
+        let episodes = PodConnection::get_episodes(&pod, &feed, &index, 10);
+        let (epi, reason) = episodes
+            .iter()
+            .find(|(e, _)| e.episodeid == 4)
+            .expect("legacy episode matched");
+        assert_eq!(*reason, Some(EpisodeMatch::Title));
+        assert_eq!(epi.guid.as_deref(), Some("old"));
+        assert_eq!(&epi.title, "Old episode");
+        assert_eq!(epi.status, EpisodeStatus::Downloaded);
+        Ok(())
+    }
+
     #[tokio::test]
     #[ignore]
     async fn test_pod_connection_get() -> Result<(), Error> {
@@ -191,12 +917,12 @@ mod tests {
             .map(|e| e.episodeid)
             .max()
             .unwrap_or(0);
-        let current_urls: HashSet<Episode> = current_episodes.into_iter().collect();
+        let current_urls: EpisodeIndex = current_episodes.into_iter().collect();
 
         let pod = Podcast::from_index(&pool, 19).await?.unwrap();
         let conn = PodConnection::new();
         let new_episodes = conn.parse_feed(&pod, &current_urls, max_epid + 1).await?;
-        assert!(new_episodes.len() > 0);
+        assert!(!new_episodes.is_empty());
         Ok(())
     }
 }
@@ -1,11 +1,25 @@
-use anyhow::Error;
+use anyhow::{format_err, Error};
 use futures::Stream;
 use postgres_query::{query, Error as PqError, FromSqlRow};
 use reqwest::Url;
-use stack_string::StackString;
-use std::collections::HashSet;
+use stack_string::{format_sstr, StackString};
+use std::{
+    collections::HashSet,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+use tokio::fs;
 
-use crate::{pgpool::PgPool, pod_connection::PodConnection};
+use crate::{
+    episode::Episode,
+    episode_index::EpisodeIndex,
+    episode_status::EpisodeStatus,
+    feed::FeedChannel,
+    filename_template::{render_filename, unique_filename},
+    pgpool::PgPool,
+    pod_connection::PodConnection,
+    podcast_namespace::{store_episode_metadata, EpisodeChapters, EpisodeTranscript},
+};
 
 #[derive(Default, Clone, Debug, FromSqlRow)]
 pub struct Podcast {
@@ -13,11 +27,37 @@ pub struct Podcast {
     pub castname: StackString,
     pub feedurl: StackString,
     pub directory: Option<StackString>,
+    pub author: Option<StackString>,
+    pub summary: Option<StackString>,
+    pub image: Option<StackString>,
+    pub explicit: Option<bool>,
+    pub block: bool,
+    pub new_feed_url: Option<StackString>,
+    pub full_episodes_only: bool,
+    pub podcast_guid: Option<StackString>,
+    pub locked: Option<bool>,
+    pub etag: Option<StackString>,
+    pub last_modified: Option<StackString>,
+    pub filename_template: Option<StackString>,
+    pub keep_latest: Option<i32>,
+    pub max_age_days: Option<i32>,
+    pub max_total_bytes: Option<i64>,
+    /// Paused podcasts are skipped by a refresh but keep their history
+    pub active: bool,
+}
+
+/// What unsubscribing from a podcast removed, or would remove in a dry run
+#[derive(Default, Clone, Debug)]
+pub struct Unsubscribed {
+    pub episodes: u64,
+    pub files: Vec<PathBuf>,
 }
 
 impl Podcast {
+    /// Subscribe to the feed at `furl`, the feed has to parse but may not
+    /// have any episodes yet
     /// # Errors
-    /// Return error if db query fails
+    /// Return error if db query fails or the feed can't be fetched
     pub async fn add_podcast(
         pool: &PgPool,
         cid: i32,
@@ -30,28 +70,64 @@ impl Podcast {
         } else if let Some(p) = Self::from_feedurl(pool, furl.as_str()).await? {
             p
         } else {
-            let pod = Self {
+            let mut pod = Self {
                 castid: cid,
                 castname: cname.into(),
                 feedurl: furl.as_str().into(),
                 directory: Some(dir.into()),
+                active: true,
+                ..Self::default()
             };
-            let episodes = PodConnection::new()
-                .parse_feed(&pod, &HashSet::new(), 0)
-                .await?;
-            assert!(!episodes.is_empty());
+            let conn = PodConnection::new();
+            let feed = conn
+                .get_feed(&mut pod)
+                .await?
+                .ok_or_else(|| format_err!("No feed returned"))?;
+            // a new show may not have published an episode yet, they are
+            // picked up by later refreshes
+            pod.update_metadata(&feed.channel);
+            // no episodes are recorded yet, the cache headers would make the
+            // first refresh skip the feed
+            pod.etag = None;
+            pod.last_modified = None;
             let query = query!(
                 r#"
-                    INSERT INTO podcasts (castid, castname, feedurl, directory)
-                    VALUES ($castid,$castname,$feedurl,$directory)
+                    INSERT INTO podcasts (
+                        castid, castname, feedurl, directory, author, summary, image,
+                        explicit, block, new_feed_url, full_episodes_only, podcast_guid,
+                        locked, etag, last_modified, filename_template, keep_latest,
+                        max_age_days, max_total_bytes, active
+                    ) VALUES (
+                        $castid, $castname, $feedurl, $directory, $author, $summary, $image,
+                        $explicit, $block, $new_feed_url, $full_episodes_only, $podcast_guid,
+                        $locked, $etag, $last_modified, $filename_template, $keep_latest,
+                        $max_age_days, $max_total_bytes, $active
+                    )
                 "#,
                 castid = pod.castid,
                 castname = pod.castname,
                 feedurl = pod.feedurl,
-                directory = pod.directory
+                directory = pod.directory,
+                author = pod.author,
+                summary = pod.summary,
+                image = pod.image,
+                explicit = pod.explicit,
+                block = pod.block,
+                new_feed_url = pod.new_feed_url,
+                full_episodes_only = pod.full_episodes_only,
+                podcast_guid = pod.podcast_guid,
+                locked = pod.locked,
+                etag = pod.etag,
+                last_modified = pod.last_modified,
+                filename_template = pod.filename_template,
+                keep_latest = pod.keep_latest,
+                max_age_days = pod.max_age_days,
+                max_total_bytes = pod.max_total_bytes,
+                active = pod.active
             );
             let conn = pool.get().await?;
-            query.fetch_one(&conn).await?
+            query.execute(&conn).await?;
+            pod
         };
         Ok(pod)
     }
@@ -62,7 +138,10 @@ impl Podcast {
         let query = query!(
             r#"
                 SELECT
-                    castid, castname, feedurl, directory
+                    castid, castname, feedurl, directory, author, summary, image,
+                    explicit, block, new_feed_url, full_episodes_only, podcast_guid,
+                    locked, etag, last_modified, filename_template, keep_latest,
+                    max_age_days, max_total_bytes, active
                 FROM podcasts
                 WHERE castid = $castid
             "#,
@@ -72,38 +151,455 @@ impl Podcast {
         query.fetch_opt(&conn).await.map_err(Into::into)
     }
 
+    /// Find a podcast by its current feed url, or by any url it was
+    /// previously known by, the podcast that moved away from it last wins
     /// # Errors
     /// Return error if db query fails
     pub async fn from_feedurl(pool: &PgPool, feedurl: &str) -> Result<Option<Self>, Error> {
         let query = query!(
             r#"
                 SELECT
-                    castid, castname, feedurl, directory
+                    castid, castname, feedurl, directory, author, summary, image,
+                    explicit, block, new_feed_url, full_episodes_only, podcast_guid,
+                    locked, etag, last_modified, filename_template, keep_latest,
+                    max_age_days, max_total_bytes, active
                 FROM podcasts
                 WHERE feedurl = $feedurl
             "#,
             feedurl = feedurl
         );
         let conn = pool.get().await?;
+        if let Some(pod) = query.fetch_opt(&conn).await? {
+            return Ok(Some(pod));
+        }
+        let query = query!(
+            r#"
+                SELECT
+                    castid, castname, feedurl, directory, author, summary, image,
+                    explicit, block, new_feed_url, full_episodes_only, podcast_guid,
+                    locked, etag, last_modified, filename_template, keep_latest,
+                    max_age_days, max_total_bytes, active
+                FROM podcasts
+                WHERE castid = (
+                    SELECT castid
+                    FROM podcast_feedurl_history
+                    WHERE feedurl = $feedurl
+                    ORDER BY changed_at DESC
+                    LIMIT 1
+                )
+            "#,
+            feedurl = feedurl
+        );
         query.fetch_opt(&conn).await.map_err(Into::into)
     }
 
+    /// Find podcasts by name, ignoring case
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn from_name(pool: &PgPool, name: &str) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT
+                    castid, castname, feedurl, directory, author, summary, image,
+                    explicit, block, new_feed_url, full_episodes_only, podcast_guid,
+                    locked, etag, last_modified, filename_template, keep_latest,
+                    max_age_days, max_total_bytes, active
+                FROM podcasts
+                WHERE lower(castname) = lower($castname)
+                ORDER BY castid
+            "#,
+            castname = name
+        );
+        let conn = pool.get().await?;
+        query.fetch(&conn).await.map_err(Into::into)
+    }
+
+    /// Find a podcast by castid, feed url or name
+    /// # Errors
+    /// Return error if db query fails or the name matches several podcasts
+    pub async fn find(pool: &PgPool, selector: &str) -> Result<Option<Self>, Error> {
+        if let Ok(cid) = selector.parse() {
+            return Self::from_index(pool, cid).await;
+        }
+        if selector.parse::<Url>().is_ok() {
+            return Self::from_feedurl(pool, selector).await;
+        }
+        let mut podcasts = Self::from_name(pool, selector).await?;
+        if podcasts.len() > 1 {
+            let castids: Vec<_> = podcasts
+                .iter()
+                .map(|p| format_sstr!("{}", p.castid))
+                .collect();
+            return Err(format_err!(
+                "{selector} matches podcasts {}",
+                castids.join(", ")
+            ));
+        }
+        Ok(podcasts.pop())
+    }
+
+    /// Follow `itunes:new-feed-url` if it points somewhere else, returns true
+    /// if the feed url changed.  Urls in `history` are not followed so two
+    /// feeds pointing at each other don't swap on every refresh.
+    pub fn follow_new_feed_url(&mut self, channel: &FeedChannel, history: &[StackString]) -> bool {
+        if let Some(new_feed_url) = channel.itunes.new_feed_url.as_ref() {
+            if new_feed_url != &self.feedurl
+                && !history.contains(new_feed_url)
+                && new_feed_url.parse::<Url>().is_ok()
+            {
+                self.feedurl = new_feed_url.clone();
+                // the cache headers belong to the old location
+                self.etag = None;
+                self.last_modified = None;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Remember a previous feed url so the podcast can still be found by it
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn add_feedurl_history(&self, pool: &PgPool, feedurl: &str) -> Result<u64, Error> {
+        let query = query!(
+            r#"
+                INSERT INTO podcast_feedurl_history (castid, feedurl)
+                VALUES ($castid, $feedurl)
+                ON CONFLICT (castid, feedurl) DO UPDATE SET changed_at = now()
+            "#,
+            castid = self.castid,
+            feedurl = feedurl
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_feedurl_history(pool: &PgPool, cid: i32) -> Result<Vec<StackString>, Error> {
+        #[derive(FromSqlRow)]
+        struct Wrap(StackString);
+
+        let query = query!(
+            r#"
+                SELECT feedurl
+                FROM podcast_feedurl_history
+                WHERE castid = $castid
+                ORDER BY changed_at
+            "#,
+            castid = cid
+        );
+        let conn = pool.get().await?;
+        let urls: Vec<Wrap> = query.fetch(&conn).await?;
+        Ok(urls.into_iter().map(|x| x.0).collect())
+    }
+
+    /// All podcasts, or only the active ones if `active_only` is set
     /// # Errors
     /// Return error if db query fails
     pub async fn get_all_podcasts(
         pool: &PgPool,
+        active_only: bool,
     ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
         let query = query!(
             r#"
             SELECT
-                castid, castname, feedurl, directory
+                castid, castname, feedurl, directory, author, summary, image,
+                explicit, block, new_feed_url, full_episodes_only, podcast_guid,
+                locked, etag, last_modified, filename_template, keep_latest,
+                max_age_days, max_total_bytes, active
             FROM podcasts
-        "#
+            WHERE active OR NOT $active_only
+            ORDER BY castid
+        "#,
+            active_only = active_only
         );
         let conn = pool.get().await?;
         query.fetch_streaming(&conn).await.map_err(Into::into)
     }
 
+    /// Copy the channel level metadata of a feed, returns true if anything
+    /// changed
+    pub fn update_metadata(&mut self, channel: &FeedChannel) -> bool {
+        let image = channel
+            .itunes
+            .image
+            .as_ref()
+            .or(channel.image.as_ref())
+            .cloned();
+        let changed = self.author != channel.itunes.author
+            || self.summary != channel.itunes.summary
+            || self.image != image
+            || self.explicit != channel.itunes.explicit
+            || self.block != channel.itunes.block
+            || self.new_feed_url != channel.itunes.new_feed_url
+            || self.podcast_guid != channel.podcast.guid
+            || self.locked != channel.podcast.locked;
+        if changed {
+            self.author.clone_from(&channel.itunes.author);
+            self.summary.clone_from(&channel.itunes.summary);
+            self.image = image;
+            self.explicit = channel.itunes.explicit;
+            self.block = channel.itunes.block;
+            self.new_feed_url.clone_from(&channel.itunes.new_feed_url);
+            self.podcast_guid.clone_from(&channel.podcast.guid);
+            self.locked = channel.podcast.locked;
+        }
+        changed
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn update_podcast(&self, pool: &PgPool) -> Result<u64, Error> {
+        let query = query!(
+            r#"
+                UPDATE podcasts
+                SET castname=$castname,feedurl=$feedurl,directory=$directory,author=$author,
+                    summary=$summary,image=$image,explicit=$explicit,block=$block,
+                    new_feed_url=$new_feed_url,full_episodes_only=$full_episodes_only,
+                    podcast_guid=$podcast_guid,locked=$locked,etag=$etag,
+                    last_modified=$last_modified,filename_template=$filename_template,
+                    keep_latest=$keep_latest,max_age_days=$max_age_days,
+                    max_total_bytes=$max_total_bytes,active=$active
+                WHERE castid=$castid
+            "#,
+            castid = self.castid,
+            castname = self.castname,
+            feedurl = self.feedurl,
+            directory = self.directory,
+            author = self.author,
+            summary = self.summary,
+            image = self.image,
+            explicit = self.explicit,
+            block = self.block,
+            new_feed_url = self.new_feed_url,
+            full_episodes_only = self.full_episodes_only,
+            podcast_guid = self.podcast_guid,
+            locked = self.locked,
+            etag = self.etag,
+            last_modified = self.last_modified,
+            filename_template = self.filename_template,
+            keep_latest = self.keep_latest,
+            max_age_days = self.max_age_days,
+            max_total_bytes = self.max_total_bytes,
+            active = self.active
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await.map_err(Into::into)
+    }
+
+    /// Record the episodes currently in the feed without downloading them:
+    /// the newest `keep_latest` episodes not downloaded yet stay `Ready`, all
+    /// older ones are marked `Skipped`.  Returns the number of ready and
+    /// skipped episodes.  The `Ready` episodes are downloaded by the next
+    /// refresh, whether or not the feed changed by then.
+    /// # Errors
+    /// Return error if fetching the feed or a db query fails
+    pub async fn catch_up(
+        &self,
+        pool: &PgPool,
+        conn: &PodConnection,
+        keep_latest: usize,
+    ) -> Result<(usize, usize), Error> {
+        let mut pod = self.clone();
+        pod.etag = None;
+        pod.last_modified = None;
+        let feed = conn
+            .get_feed(&mut pod)
+            .await?
+            .ok_or_else(|| format_err!("No feed returned"))?;
+        let index: EpisodeIndex = Episode::get_all_episodes(pool, self.castid)
+            .await?
+            .into_iter()
+            .collect();
+        let max_epid = Episode::get_max_epid(pool).await?;
+        let mut pending: Vec<_> = index
+            .episodes()
+            .iter()
+            .filter(|e| e.status == EpisodeStatus::Ready && e.checksum.is_none())
+            .cloned()
+            .map(|e| (e, true))
+            .collect();
+        pending.extend(
+            PodConnection::get_episodes(self, &feed, &index, max_epid + 1)
+                .into_iter()
+                .filter_map(|(e, reason)| {
+                    if reason.is_none() {
+                        Some((e, false))
+                    } else {
+                        None
+                    }
+                }),
+        );
+        let mut taken: HashSet<_> = index
+            .episodes()
+            .iter()
+            .filter_map(|e| e.filename.clone())
+            .collect();
+        let template = self.filename_template.as_ref().map(StackString::as_str);
+        let mut counts = (0, 0);
+        for (mut epi, exists) in catch_up_episodes(pending, keep_latest)? {
+            if epi.status == EpisodeStatus::Ready {
+                counts.0 += 1;
+                if let (None, Some(directory)) = (epi.filename.as_ref(), self.directory.as_ref()) {
+                    let filename = render_filename(template, &epi)?;
+                    let directory = Path::new(directory.as_str());
+                    epi.filename = Some(unique_filename(&filename, directory, &mut taken));
+                }
+            } else {
+                counts.1 += 1;
+            }
+            if exists {
+                epi.update_episode(pool).await?;
+                continue;
+            }
+            epi.insert_episode(pool).await?;
+            if let Some(item) = feed
+                .items
+                .iter()
+                .find(|item| item.enclosure.as_ref().is_some_and(|e| e.url == epi.epurl))
+            {
+                store_episode_metadata(pool, epi.castid, epi.episodeid, &item.podcast).await?;
+            }
+        }
+        Ok(counts)
+    }
+
+    /// Remove the podcast together with its episodes and feed metadata,
+    /// returns the number of episodes removed
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn remove(&self, pool: &PgPool) -> Result<u64, Error> {
+        let mut conn = pool.get().await?;
+        let tran = conn.transaction().await?;
+        let mut removed = 0;
+        for table in [
+            "episodes",
+            "episode_chapters",
+            "episode_transcripts",
+            "podcast_persons",
+            "podcast_funding",
+            "podcast_feedurl_history",
+            "podcasts",
+        ] {
+            let query = format_sstr!("DELETE FROM {table} WHERE castid = $1");
+            let count = tran.execute(query.as_str(), &[&self.castid]).await?;
+            if table == "episodes" {
+                removed = count;
+            }
+        }
+        tran.commit().await?;
+        Ok(removed)
+    }
+
+    /// Files in the podcast directory belonging to `episodes`: the episodes
+    /// themselves, unfinished `.part` downloads and the chapters and
+    /// transcripts stored next to them
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn episode_files(
+        &self,
+        pool: &PgPool,
+        episodes: &[Episode],
+    ) -> Result<Vec<PathBuf>, Error> {
+        let mut files = Vec::new();
+        let Some(directory) = self.directory.as_ref() else {
+            return Ok(files);
+        };
+        let directory = Path::new(directory.as_str());
+        for epi in episodes {
+            let filename = epi.file_name()?;
+            let stem = Path::new(filename.as_str())
+                .file_stem()
+                .map_or_else(|| filename.clone(), |s| s.to_string_lossy().into());
+            let mut names = vec![
+                format_sstr!("{filename}.part"),
+                format_sstr!("{filename}.part.validator"),
+                filename,
+            ];
+            if let Some(chapters) =
+                EpisodeChapters::get_by_episode(pool, self.castid, epi.episodeid).await?
+            {
+                names.push(chapters.filename(&stem));
+            }
+            for transcript in
+                EpisodeTranscript::get_by_episode(pool, self.castid, epi.episodeid).await?
+            {
+                names.push(transcript.filename(&stem));
+            }
+            for name in names {
+                let path = directory.join(name.as_str());
+                if path.exists() && !files.contains(&path) {
+                    files.push(path);
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    /// Unsubscribe from the podcast, removing it and its episodes from the
+    /// database.  With `delete_files` the files of the episodes are deleted
+    /// as well, along with the podcast directory if that leaves it empty.  A
+    /// dry run changes nothing and returns what would be removed.  The files
+    /// are deleted before the database rows, if any of them can't be removed
+    /// the podcast stays subscribed so unsubscribing can be retried.
+    /// # Errors
+    /// Return error if db query fails or a file can't be removed
+    pub async fn unsubscribe(
+        &self,
+        pool: &PgPool,
+        delete_files: bool,
+        dry_run: bool,
+    ) -> Result<Unsubscribed, Error> {
+        let episodes = Episode::get_all_episodes(pool, self.castid).await?;
+        let files = if delete_files {
+            self.episode_files(pool, &episodes).await?
+        } else {
+            Vec::new()
+        };
+        if dry_run {
+            return Ok(Unsubscribed {
+                episodes: episodes.len() as u64,
+                files,
+            });
+        }
+        let mut failures = Vec::new();
+        for file in &files {
+            match fs::remove_file(file).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => failures.push(format_sstr!("{}: {e}", file.display())),
+            }
+        }
+        if !failures.is_empty() {
+            return Err(format_err!(
+                "Failed to remove {} files, {} is still subscribed\n{}",
+                failures.len(),
+                self.castname,
+                failures.join("\n")
+            ));
+        }
+        let episodes = self.remove(pool).await?;
+        if let Some(directory) = self.directory.as_ref().filter(|_| delete_files) {
+            // only succeeds if nothing else was stored in the directory
+            fs::remove_dir(directory.as_str()).await.ok();
+        }
+        Ok(Unsubscribed { episodes, files })
+    }
+
+    /// Pause or resume refreshing the podcast, returns false if it already
+    /// was in that state
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn set_active(&mut self, pool: &PgPool, active: bool) -> Result<bool, Error> {
+        if self.active == active {
+            return Ok(false);
+        }
+        self.active = active;
+        self.update_podcast(pool).await?;
+        Ok(true)
+    }
+
     /// # Errors
     /// Return error if db query fails
     pub async fn get_max_castid(pool: &PgPool) -> Result<Option<i32>, Error> {
@@ -117,12 +613,97 @@ impl Podcast {
     }
 }
 
+/// Keep the newest `keep_latest` of the episodes `Ready` and skip the rest,
+/// episodes without a publication date count as the oldest
+fn catch_up_episodes<T>(
+    mut episodes: Vec<(Episode, T)>,
+    keep_latest: usize,
+) -> Result<Vec<(Episode, T)>, Error> {
+    episodes.sort_by(|(a, _), (b, _)| {
+        b.pubdate
+            .is_some()
+            .cmp(&a.pubdate.is_some())
+            .then(b.pubdate.cmp(&a.pubdate))
+            .then(b.episodeid.cmp(&a.episodeid))
+    });
+    for (epi, _) in episodes.iter_mut().skip(keep_latest) {
+        epi.set_status(EpisodeStatus::Skipped)?;
+    }
+    Ok(episodes)
+}
+
 #[cfg(test)]
 mod tests {
     use anyhow::Error;
     use log::debug;
 
-    use crate::{config::Config, pgpool::PgPool, podcast::Podcast};
+    use time::macros::datetime;
+
+    use crate::{
+        config::Config,
+        episode::Episode,
+        episode_status::EpisodeStatus,
+        feed::FeedChannel,
+        pgpool::PgPool,
+        podcast::{catch_up_episodes, Podcast},
+    };
+
+    #[test]
+    fn test_follow_new_feed_url() {
+        let mut pod = Podcast {
+            feedurl: "https://old.example.com/feed.xml".into(),
+            etag: Some("\"abc\"".into()),
+            ..Podcast::default()
+        };
+        let mut channel = FeedChannel::default();
+        assert!(!pod.follow_new_feed_url(&channel, &[]));
+
+        channel.itunes.new_feed_url = Some("not a url".into());
+        assert!(!pod.follow_new_feed_url(&channel, &[]));
+
+        channel.itunes.new_feed_url = Some("https://new.example.com/feed.xml".into());
+        assert!(pod.follow_new_feed_url(&channel, &[]));
+        assert_eq!(&pod.feedurl, "https://new.example.com/feed.xml");
+        assert_eq!(pod.etag, None);
+        assert!(!pod.follow_new_feed_url(&channel, &[]));
+
+        // the new feed points back at the old one
+        let history = ["https://old.example.com/feed.xml".into()];
+        channel.itunes.new_feed_url = Some("https://old.example.com/feed.xml".into());
+        assert!(!pod.follow_new_feed_url(&channel, &history));
+        assert_eq!(&pod.feedurl, "https://new.example.com/feed.xml");
+    }
+
+    #[test]
+    fn test_catch_up_episodes() -> Result<(), Error> {
+        let episodes: Vec<_> = [
+            (1, Some(datetime!(2014-01-01 0:00 UTC))),
+            (2, None),
+            (3, Some(datetime!(2024-01-01 0:00 UTC))),
+            (4, Some(datetime!(2019-01-01 0:00 UTC))),
+        ]
+        .iter()
+        .map(|(episodeid, pubdate)| {
+            let epi = Episode {
+                episodeid: *episodeid,
+                pubdate: *pubdate,
+                ..Episode::default()
+            };
+            (epi, ())
+        })
+        .collect();
+        let ready = |episodes: Vec<(Episode, ())>| -> Vec<i32> {
+            episodes
+                .into_iter()
+                .filter(|(e, _)| e.status == EpisodeStatus::Ready)
+                .map(|(e, _)| e.episodeid)
+                .collect()
+        };
+        assert!(ready(catch_up_episodes(episodes.clone(), 0)?).is_empty());
+        assert_eq!(ready(catch_up_episodes(episodes.clone(), 2)?), vec![3, 4]);
+        assert_eq!(ready(catch_up_episodes(episodes, 10)?).len(), 4);
+        Ok(())
+    }
 
     #[tokio::test]
     #[ignore]
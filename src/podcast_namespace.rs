@@ -0,0 +1,426 @@
+use anyhow::Error;
+use log::debug;
+use postgres_query::{query, FromSqlRow};
+use reqwest::Url;
+use stack_string::{format_sstr, StackString};
+use std::path::Path;
+
+use crate::{
+    feed::{FeedChapters, FeedFunding, FeedPerson, FeedTranscript, PodcastChannel, PodcastItem},
+    pgpool::PgPool,
+    pod_connection::PodConnection,
+};
+
+#[derive(Default, Clone, Debug, FromSqlRow, PartialEq, Eq)]
+pub struct EpisodeChapters {
+    pub castid: i32,
+    pub episodeid: i32,
+    pub url: StackString,
+    pub chaptype: Option<StackString>,
+}
+
+#[derive(Default, Clone, Debug, FromSqlRow, PartialEq, Eq)]
+pub struct EpisodeTranscript {
+    pub castid: i32,
+    pub episodeid: i32,
+    pub url: StackString,
+    pub transtype: Option<StackString>,
+    pub language: Option<StackString>,
+    pub rel: Option<StackString>,
+}
+
+/// Persons are either credited on the whole podcast (`episodeid` is `None`)
+/// or on a single episode
+#[derive(Default, Clone, Debug, FromSqlRow, PartialEq, Eq)]
+pub struct PodcastPerson {
+    pub castid: i32,
+    pub episodeid: Option<i32>,
+    pub name: StackString,
+    pub role: Option<StackString>,
+    pub grp: Option<StackString>,
+    pub img: Option<StackString>,
+    pub href: Option<StackString>,
+}
+
+#[derive(Default, Clone, Debug, FromSqlRow, PartialEq, Eq)]
+pub struct PodcastFunding {
+    pub castid: i32,
+    pub url: StackString,
+    pub message: Option<StackString>,
+}
+
+impl EpisodeChapters {
+    #[must_use]
+    pub fn from_feed(castid: i32, episodeid: i32, chapters: &FeedChapters) -> Self {
+        Self {
+            castid,
+            episodeid,
+            url: chapters.url.clone(),
+            chaptype: chapters.chaptype.clone(),
+        }
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_episode(
+        pool: &PgPool,
+        castid: i32,
+        episodeid: i32,
+    ) -> Result<Option<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT castid, episodeid, url, chaptype
+                FROM episode_chapters
+                WHERE castid = $castid AND episodeid = $episodeid
+            "#,
+            castid = castid,
+            episodeid = episodeid
+        );
+        let conn = pool.get().await?;
+        query.fetch_opt(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn upsert(&self, pool: &PgPool) -> Result<u64, Error> {
+        let query = query!(
+            r#"
+                INSERT INTO episode_chapters (castid, episodeid, url, chaptype)
+                VALUES ($castid, $episodeid, $url, $chaptype)
+                ON CONFLICT (castid, episodeid)
+                DO UPDATE SET url=EXCLUDED.url, chaptype=EXCLUDED.chaptype
+            "#,
+            castid = self.castid,
+            episodeid = self.episodeid,
+            url = self.url,
+            chaptype = self.chaptype
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await.map_err(Into::into)
+    }
+
+    /// Chapters are stored as `<episode stem>.chapters.json`
+    #[must_use]
+    pub fn filename(&self, stem: &str) -> StackString {
+        format_sstr!("{stem}.chapters.json")
+    }
+}
+
+impl EpisodeTranscript {
+    #[must_use]
+    pub fn from_feed(castid: i32, episodeid: i32, transcript: &FeedTranscript) -> Self {
+        Self {
+            castid,
+            episodeid,
+            url: transcript.url.clone(),
+            transtype: transcript.transtype.clone(),
+            language: transcript.language.clone(),
+            rel: transcript.rel.clone(),
+        }
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_episode(
+        pool: &PgPool,
+        castid: i32,
+        episodeid: i32,
+    ) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT castid, episodeid, url, transtype, language, rel
+                FROM episode_transcripts
+                WHERE castid = $castid AND episodeid = $episodeid
+            "#,
+            castid = castid,
+            episodeid = episodeid
+        );
+        let conn = pool.get().await?;
+        query.fetch(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn upsert(&self, pool: &PgPool) -> Result<u64, Error> {
+        let query = query!(
+            r#"
+                INSERT INTO episode_transcripts (
+                    castid, episodeid, url, transtype, language, rel
+                ) VALUES (
+                    $castid, $episodeid, $url, $transtype, $language, $rel
+                )
+                ON CONFLICT (castid, episodeid, url)
+                DO UPDATE SET transtype=EXCLUDED.transtype, language=EXCLUDED.language,
+                    rel=EXCLUDED.rel
+            "#,
+            castid = self.castid,
+            episodeid = self.episodeid,
+            url = self.url,
+            transtype = self.transtype,
+            language = self.language,
+            rel = self.rel
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await.map_err(Into::into)
+    }
+
+    fn extension(&self) -> &'static str {
+        match self.transtype.as_ref().map(StackString::as_str) {
+            Some("text/vtt") => "vtt",
+            Some("application/x-subrip" | "application/srt") => "srt",
+            Some("application/json") => "json",
+            Some("text/html") => "html",
+            _ => "txt",
+        }
+    }
+
+    /// Transcripts are stored as `<episode stem>[.<language>].<ext>`, only
+    /// ascii alphanumerics and `-` of the language are kept as it comes
+    /// straight from the feed
+    #[must_use]
+    pub fn filename(&self, stem: &str) -> StackString {
+        let ext = self.extension();
+        let language: String = self
+            .language
+            .as_ref()
+            .map(|l| {
+                l.chars()
+                    .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+                    .collect()
+            })
+            .unwrap_or_default();
+        if language.is_empty() {
+            format_sstr!("{stem}.{ext}")
+        } else {
+            format_sstr!("{stem}.{language}.{ext}")
+        }
+    }
+}
+
+impl PodcastPerson {
+    #[must_use]
+    pub fn from_feed(castid: i32, episodeid: Option<i32>, person: &FeedPerson) -> Self {
+        Self {
+            castid,
+            episodeid,
+            name: person.name.clone(),
+            role: person.role.clone(),
+            grp: person.group.clone(),
+            img: person.img.clone(),
+            href: person.href.clone(),
+        }
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_podcast(pool: &PgPool, castid: i32) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT castid, episodeid, name, role, grp, img, href
+                FROM podcast_persons
+                WHERE castid = $castid
+            "#,
+            castid = castid
+        );
+        let conn = pool.get().await?;
+        query.fetch(&conn).await.map_err(Into::into)
+    }
+
+    /// Replace the persons credited on a podcast or on one of its episodes
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn replace(
+        pool: &PgPool,
+        castid: i32,
+        episodeid: Option<i32>,
+        persons: &[Self],
+    ) -> Result<(), Error> {
+        let mut conn = pool.get().await?;
+        let tran = conn.transaction().await?;
+        let query = query!(
+            r#"
+                DELETE FROM podcast_persons
+                WHERE castid = $castid AND episodeid IS NOT DISTINCT FROM $episodeid
+            "#,
+            castid = castid,
+            episodeid = episodeid
+        );
+        query.execute(&tran).await?;
+        for person in persons {
+            let query = query!(
+                r#"
+                    INSERT INTO podcast_persons (castid, episodeid, name, role, grp, img, href)
+                    VALUES ($castid, $episodeid, $name, $role, $grp, $img, $href)
+                "#,
+                castid = castid,
+                episodeid = episodeid,
+                name = person.name,
+                role = person.role,
+                grp = person.grp,
+                img = person.img,
+                href = person.href
+            );
+            query.execute(&tran).await?;
+        }
+        tran.commit().await.map_err(Into::into)
+    }
+}
+
+impl PodcastFunding {
+    #[must_use]
+    pub fn from_feed(castid: i32, funding: &FeedFunding) -> Self {
+        Self {
+            castid,
+            url: funding.url.clone(),
+            message: funding.message.clone(),
+        }
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_podcast(pool: &PgPool, castid: i32) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT castid, url, message
+                FROM podcast_funding
+                WHERE castid = $castid
+            "#,
+            castid = castid
+        );
+        let conn = pool.get().await?;
+        query.fetch(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn replace(pool: &PgPool, castid: i32, funding: &[Self]) -> Result<(), Error> {
+        let mut conn = pool.get().await?;
+        let tran = conn.transaction().await?;
+        let query = query!(
+            "DELETE FROM podcast_funding WHERE castid = $castid",
+            castid = castid
+        );
+        query.execute(&tran).await?;
+        for fund in funding {
+            let query = query!(
+                r#"
+                    INSERT INTO podcast_funding (castid, url, message)
+                    VALUES ($castid, $url, $message)
+                    ON CONFLICT (castid, url) DO UPDATE SET message=EXCLUDED.message
+                "#,
+                castid = castid,
+                url = fund.url,
+                message = fund.message
+            );
+            query.execute(&tran).await?;
+        }
+        tran.commit().await.map_err(Into::into)
+    }
+}
+
+/// # Errors
+/// Return error if db query fails
+pub async fn store_podcast_metadata(
+    pool: &PgPool,
+    castid: i32,
+    podcast: &PodcastChannel,
+) -> Result<(), Error> {
+    let funding: Vec<_> = podcast
+        .funding
+        .iter()
+        .map(|f| PodcastFunding::from_feed(castid, f))
+        .collect();
+    PodcastFunding::replace(pool, castid, &funding).await?;
+    let persons: Vec<_> = podcast
+        .persons
+        .iter()
+        .map(|p| PodcastPerson::from_feed(castid, None, p))
+        .collect();
+    PodcastPerson::replace(pool, castid, None, &persons).await
+}
+
+/// # Errors
+/// Return error if db query fails
+pub async fn store_episode_metadata(
+    pool: &PgPool,
+    castid: i32,
+    episodeid: i32,
+    podcast: &PodcastItem,
+) -> Result<(), Error> {
+    if let Some(chapters) = podcast.chapters.as_ref() {
+        EpisodeChapters::from_feed(castid, episodeid, chapters)
+            .upsert(pool)
+            .await?;
+    }
+    for transcript in &podcast.transcripts {
+        EpisodeTranscript::from_feed(castid, episodeid, transcript)
+            .upsert(pool)
+            .await?;
+    }
+    let persons: Vec<_> = podcast
+        .persons
+        .iter()
+        .map(|p| PodcastPerson::from_feed(castid, Some(episodeid), p))
+        .collect();
+    PodcastPerson::replace(pool, castid, Some(episodeid), &persons).await
+}
+
+/// Download chapter and transcript files next to the episode audio, files
+/// that already exist are left alone
+/// # Errors
+/// Return error if api call fails
+pub async fn download_episode_extras(
+    conn: &PodConnection,
+    directory: &Path,
+    stem: &str,
+    chapters: Option<&EpisodeChapters>,
+    transcripts: &[EpisodeTranscript],
+) -> Result<Vec<StackString>, Error> {
+    let mut files = Vec::new();
+    let chapters = chapters.map(|c| (c.url.as_str(), c.filename(stem)));
+    let transcripts = transcripts
+        .iter()
+        .map(|t| (t.url.as_str(), t.filename(stem)));
+    for (url, filename) in chapters.into_iter().chain(transcripts) {
+        let outfile = directory.join(filename.as_str());
+        if outfile.exists() {
+            continue;
+        }
+        let url: Url = url.parse()?;
+        debug!("download {url} {}", outfile.display());
+        conn.dump_to_file(&url, &outfile, None).await?;
+        files.push(filename);
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::podcast_namespace::{EpisodeChapters, EpisodeTranscript};
+
+    #[test]
+    fn test_extra_filenames() {
+        let chapters = EpisodeChapters {
+            url: "https://example.com/chapters.json".into(),
+            ..EpisodeChapters::default()
+        };
+        assert_eq!(&chapters.filename("episode_1"), "episode_1.chapters.json");
+
+        let mut transcript = EpisodeTranscript {
+            url: "https://example.com/transcript.vtt".into(),
+            transtype: Some("text/vtt".into()),
+            ..EpisodeTranscript::default()
+        };
+        assert_eq!(&transcript.filename("episode_1"), "episode_1.vtt");
+        transcript.transtype = Some("application/x-subrip".into());
+        transcript.language = Some("es".into());
+        assert_eq!(&transcript.filename("episode_1"), "episode_1.es.srt");
+        transcript.language = Some("../../pt-BR".into());
+        assert_eq!(&transcript.filename("episode_1"), "episode_1.pt-BR.srt");
+        transcript.language = Some("/..".into());
+        assert_eq!(&transcript.filename("episode_1"), "episode_1.srt");
+        transcript.transtype = None;
+        transcript.language = None;
+        assert_eq!(&transcript.filename("episode_1"), "episode_1.txt");
+    }
+}
@@ -1,15 +1,36 @@
 use anyhow::{format_err, Error};
-use clap::Parser;
-use futures::{future::try_join_all, TryStreamExt};
+use clap::{value_parser, Args, Parser, Subcommand};
+use futures::TryStreamExt;
 use refinery::embed_migrations;
 use reqwest::Url;
 use stack_string::{format_sstr, StackString};
-use std::{collections::HashSet, path::Path, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::{self, Write},
+    path::Path,
+    sync::Arc,
+};
 use stdout_channel::StdoutChannel;
+use time::{macros::format_description, Date, OffsetDateTime};
 
 use crate::{
-    config::Config, episode::Episode, episode_status::EpisodeStatus, get_md5sum, pgpool::PgPool,
-    pod_connection::PodConnection, podcast::Podcast,
+    config::Config,
+    episode::{Episode, EpisodeFilter},
+    episode_index::{EpisodeIndex, EpisodeMatch},
+    episode_status::EpisodeStatus,
+    feed::Feed,
+    filename_template::{render_filename, unique_filename, validate_template, ReservedNames},
+    get_md5sum,
+    listing::{format_listing, format_record, Listing, OutputFormat},
+    pgpool::PgPool,
+    pod_connection::PodConnection,
+    podcast::Podcast,
+    podcast_namespace::{
+        download_episode_extras, store_episode_metadata, store_podcast_metadata, EpisodeChapters,
+        EpisodeTranscript,
+    },
+    retention::{cleanup_podcast, expired_pending},
+    work_queue::run_queue,
 };
 
 embed_migrations!("migrations");
@@ -18,22 +39,289 @@ fn parse_url(s: &str) -> Result<Url, String> {
     s.parse().map_err(|e| format!("{e}"))
 }
 
+fn parse_status(s: &str) -> Result<EpisodeStatus, String> {
+    let mut chars = s.chars();
+    let status: String = chars
+        .next()
+        .map(|c| c.to_uppercase().chain(chars.flat_map(char::to_lowercase)))
+        .into_iter()
+        .flatten()
+        .collect();
+    status.parse().map_err(|e| format!("{e}"))
+}
+
+fn parse_date(s: &str) -> Result<OffsetDateTime, String> {
+    Date::parse(s, format_description!("[year]-[month]-[day]"))
+        .map(|d| d.midnight().assume_utc())
+        .map_err(|e| format!("{e}"))
+}
+
 #[derive(Parser, Debug)]
 pub struct PodcatchOpts {
-    #[clap(short = 'l', long = "list")]
-    do_list: bool,
-    #[clap(short = 'a', long = "add")]
-    do_add: bool,
-    #[clap(short = 'n', long = "name")]
-    podcast_name: Option<StackString>,
-    #[clap(short = 'u', long = "url", value_parser = parse_url)]
-    podcast_url: Option<Url>,
-    #[clap(short = 'i', long = "castid")]
-    castid: Option<i32>,
+    /// Refresh all podcasts when no command is given
+    #[clap(subcommand)]
+    command: Option<PodcatchCommand>,
+}
+
+#[derive(Subcommand, Debug)]
+enum PodcatchCommand {
+    /// Refresh all podcasts and download their new episodes
+    Refresh {
+        /// Only refresh this podcast, given by castid, name or feed url.
+        /// Paused podcasts can be refreshed this way.
+        #[clap(short = 'p', long = "podcast")]
+        podcast: Option<StackString>,
+        /// Only refresh the feed metadata, new episodes are recorded as
+        /// `Ready` and downloaded by the next refresh or `download`, even if
+        /// the feed hasn't changed by then
+        #[clap(long = "no-download")]
+        no_download: bool,
+    },
+    /// Download the queued episodes of one or all podcasts
+    Download {
+        #[clap(short = 'i', long = "castid")]
+        castid: Option<i32>,
+        /// Download a single episode, queueing it again if it failed or was
+        /// skipped
+        #[clap(short = 'e', long = "episodeid", requires = "castid")]
+        episodeid: Option<i32>,
+    },
+    /// Subscribe to a podcast
+    Add {
+        #[clap(short = 'n', long = "name")]
+        name: StackString,
+        #[clap(short = 'u', long = "url", value_parser = parse_url)]
+        url: Url,
+        /// Defaults to one more than the highest castid in use
+        #[clap(short = 'i', long = "castid")]
+        castid: Option<i32>,
+        #[clap(flatten)]
+        settings: PodcastSettings,
+        /// Record the episodes in the feed without downloading them, only
+        /// the newest N are kept for download
+        #[clap(long = "catch-up")]
+        catch_up: Option<usize>,
+    },
+    /// Unsubscribe from a podcast, removing it and its episodes from the
+    /// database
+    Remove {
+        #[clap(short = 'i', long = "castid")]
+        castid: i32,
+        /// Also delete the downloaded episodes, they are kept by default
+        #[clap(long = "delete-files")]
+        delete_files: bool,
+        /// Pause the podcast instead, keeping it and its episodes in the
+        /// database
+        #[clap(long = "keep-history", conflicts_with = "delete_files")]
+        keep_history: bool,
+        /// Only show what would be removed
+        #[clap(long = "dry-run")]
+        dry_run: bool,
+        /// Don't ask for confirmation
+        #[clap(short = 'y', long = "yes")]
+        yes: bool,
+    },
+    /// Stop refreshing a podcast without removing it
+    Pause {
+        #[clap(short = 'i', long = "castid")]
+        castid: i32,
+    },
+    /// Refresh a paused podcast again
+    Resume {
+        #[clap(short = 'i', long = "castid")]
+        castid: i32,
+    },
+    /// List all podcasts
+    List {
+        #[clap(flatten)]
+        listing: ListingOpts,
+    },
+    /// List the episodes of a podcast, or of all podcasts
+    Episodes {
+        #[clap(short = 'i', long = "castid")]
+        castid: Option<i32>,
+        #[clap(flatten)]
+        filter: EpisodeFilterOpts,
+        #[clap(flatten)]
+        listing: ListingOpts,
+    },
+    /// Show the settings, feed url history and episode counts of a podcast
+    Show {
+        #[clap(short = 'i', long = "castid")]
+        castid: i32,
+    },
+    /// Change the settings of a podcast or the status of one of its episodes
+    Set {
+        #[clap(short = 'i', long = "castid")]
+        castid: i32,
+        #[clap(short = 'n', long = "name")]
+        name: Option<StackString>,
+        /// New feed url, the old one is kept in the feed url history
+        #[clap(short = 'u', long = "url", value_parser = parse_url)]
+        url: Option<Url>,
+        #[clap(flatten)]
+        settings: PodcastSettings,
+        #[clap(short = 'e', long = "episodeid", requires = "status")]
+        episodeid: Option<i32>,
+        /// New status of the episode given by `--episodeid`, e.g. `played`
+        #[clap(short = 's', long = "status", value_parser = parse_status, requires = "episodeid")]
+        status: Option<EpisodeStatus>,
+    },
+    /// Re-queue episodes that failed to download, including the ones a
+    /// refresh gave up on after the `max_download_attempts` config setting
+    Requeue {
+        /// Only re-queue the episodes of this podcast
+        #[clap(short = 'i', long = "castid")]
+        castid: Option<i32>,
+    },
+    /// Record the episodes in the feed of a podcast without downloading
+    /// them, only the newest N are kept for download
+    CatchUp {
+        #[clap(short = 'i', long = "castid")]
+        castid: i32,
+        #[clap(short = 'k', long = "keep-latest", default_value = "0")]
+        keep_latest: usize,
+    },
+    /// Run the database migrations
+    Migrate,
+}
+
+/// Which episodes `episodes` lists
+#[derive(Args, Debug, Default)]
+struct EpisodeFilterOpts {
+    /// Comma separated states, e.g. `ready,error`
+    #[clap(short = 's', long = "status", value_parser = parse_status, value_delimiter = ',')]
+    status: Vec<EpisodeStatus>,
+    /// Published on or after this date, `YYYY-MM-DD`
+    #[clap(long = "since", value_parser = parse_date)]
+    since: Option<OffsetDateTime>,
+    /// Published before this date, `YYYY-MM-DD`
+    #[clap(long = "until", value_parser = parse_date)]
+    until: Option<OffsetDateTime>,
+    /// Title contains this text, ignoring case
+    #[clap(long = "title")]
+    title: Option<StackString>,
+    /// Title matches this regular expression, ignoring case
+    #[clap(long = "title-regex")]
+    title_regex: Option<StackString>,
+    /// Print at most this many episodes
+    #[clap(long = "limit", value_parser = value_parser!(i64).range(0..))]
+    limit: Option<i64>,
+    /// Skip this many episodes, in the order they are printed
+    #[clap(long = "offset", value_parser = value_parser!(i64).range(0..))]
+    offset: Option<i64>,
+}
+
+impl EpisodeFilterOpts {
+    fn into_filter(self, castid: Option<i32>) -> EpisodeFilter {
+        EpisodeFilter {
+            castid,
+            status: self.status,
+            since: self.since,
+            until: self.until,
+            title: self.title,
+            title_regex: self.title_regex,
+            sort: None,
+            reverse: false,
+            limit: self.limit,
+            offset: self.offset,
+        }
+    }
+}
+
+/// How `list` and `episodes` print their output
+#[derive(Args, Debug, Default)]
+struct ListingOpts {
+    #[clap(short = 'f', long = "format", value_enum, default_value_t)]
+    format: OutputFormat,
+    /// Comma separated columns to print, a table shows a few essential
+    /// columns by default and the other formats every column
+    #[clap(short = 'c', long = "columns", value_delimiter = ',')]
+    columns: Vec<StackString>,
+    /// Column to sort by
+    #[clap(long = "sort")]
+    sort: Option<StackString>,
+    /// Reverse the order
+    #[clap(short = 'r', long = "reverse")]
+    reverse: bool,
+}
+
+impl ListingOpts {
+    fn format<T: Listing>(&self, items: &[T]) -> Result<Vec<StackString>, Error> {
+        format_listing(
+            items,
+            self.format,
+            &self.columns,
+            self.sort.as_ref().map(StackString::as_str),
+            self.reverse,
+        )
+    }
+}
+
+/// Per podcast settings shared by `add` and `set`
+#[derive(Args, Debug, Default)]
+struct PodcastSettings {
     #[clap(short = 'd', long = "directory")]
     directory: Option<StackString>,
-    #[clap(long = "run-migrations")]
-    run_migrations: bool,
+    /// File name template for new episodes, e.g.
+    /// `{date}_{season}x{episode}_{title}.{ext}`
+    #[clap(short = 't', long = "filename-template")]
+    filename_template: Option<StackString>,
+    /// Only keep the latest N downloaded episodes on disk
+    #[clap(long = "keep-latest", value_parser = value_parser!(i32).range(0..))]
+    keep_latest: Option<i32>,
+    /// Delete downloaded episodes published more than N days ago
+    #[clap(long = "max-age-days", value_parser = value_parser!(i32).range(0..))]
+    max_age_days: Option<i32>,
+    /// Delete the oldest downloaded episodes once the podcast uses more
+    /// than N bytes
+    #[clap(long = "max-total-bytes", value_parser = value_parser!(i64).range(0..))]
+    max_total_bytes: Option<i64>,
+    /// Ignore trailers and bonus episodes
+    #[clap(long = "full-episodes-only")]
+    full_episodes_only: Option<bool>,
+}
+
+impl PodcastSettings {
+    /// Copy the settings that were given to `pod`, returns true if anything
+    /// changed
+    fn apply(self, pod: &mut Podcast) -> Result<bool, Error> {
+        if let Some(template) = self.filename_template.as_ref() {
+            validate_template(template)?;
+        }
+        let mut changed = false;
+        if self.directory.is_some() {
+            pod.directory = self.directory;
+            changed = true;
+        }
+        if self.filename_template.is_some() {
+            pod.filename_template = self.filename_template;
+            changed = true;
+        }
+        if self.keep_latest.is_some() {
+            pod.keep_latest = self.keep_latest;
+            changed = true;
+        }
+        if self.max_age_days.is_some() {
+            pod.max_age_days = self.max_age_days;
+            changed = true;
+        }
+        if self.max_total_bytes.is_some() {
+            pod.max_total_bytes = self.max_total_bytes;
+            changed = true;
+        }
+        if let Some(full_episodes_only) = self.full_episodes_only {
+            pod.full_episodes_only = full_episodes_only;
+            changed = true;
+        }
+        Ok(changed)
+    }
+}
+
+async fn get_podcast(pool: &PgPool, castid: i32) -> Result<Podcast, Error> {
+    Podcast::from_index(pool, castid)
+        .await?
+        .ok_or_else(|| format_err!("No podcast {castid}"))
 }
 
 impl PodcatchOpts {
@@ -44,170 +332,1000 @@ impl PodcatchOpts {
 
         let config = Config::init_config()?;
         let pool = PgPool::new(&config.database_url)?;
-
-        if opts.run_migrations {
-            let mut conn = pool.get().await?;
-            migrations::runner().run_async(&mut **conn).await?;
-            return Ok(());
-        }
-
         let stdout = StdoutChannel::new();
 
-        if opts.do_list {
-            if let Some(castid) = opts.castid {
-                for eps in &Episode::get_all_episodes(&pool, castid).await? {
-                    stdout.send(format_sstr!("{eps:?}"));
+        let mut failures = Vec::new();
+        let command = opts.command.unwrap_or(PodcatchCommand::Refresh {
+            podcast: None,
+            no_download: false,
+        });
+        match command {
+            PodcatchCommand::Refresh {
+                podcast,
+                no_download,
+            } => {
+                let podcast = match podcast {
+                    Some(selector) => Some(
+                        Podcast::find(&pool, &selector)
+                            .await?
+                            .ok_or_else(|| format_err!("No podcast {selector}"))?,
+                    ),
+                    None => None,
+                };
+                failures =
+                    process_all_podcasts(&pool, &config, &stdout, podcast, !no_download).await?;
+            }
+            PodcatchCommand::Download { castid, episodeid } => {
+                failures = download_queued(&pool, &config, &stdout, castid, episodeid).await?;
+            }
+            PodcatchCommand::Add {
+                name,
+                url,
+                castid,
+                settings,
+                catch_up: keep_latest,
+            } => {
+                if let Some(pod) = Podcast::from_feedurl(&pool, url.as_str()).await? {
+                    return Err(format_err!(
+                        "{url} is already subscribed as {} {}",
+                        pod.castid,
+                        pod.castname
+                    ));
                 }
-            } else {
-                let mut stream = Box::pin(Podcast::get_all_podcasts(&pool).await?);
-                while let Some(pod) = stream.try_next().await? {
-                    stdout.send(format_sstr!("{pod:?}"));
-                }
-            }
-        } else if opts.do_add {
-            if let Some(podcast_name) = opts.podcast_name.as_ref() {
-                if let Some(podcast_url) = opts.podcast_url.as_ref() {
-                    let castid = match opts.castid {
-                        Some(c) => c,
-                        None => Podcast::get_max_castid(&pool).await?.unwrap_or(0),
-                    };
-                    let directory = opts.directory.unwrap_or_else(|| {
-                        let home_dir = std::env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
-                        format_sstr!("{home_dir}/{podcast_name}")
-                    });
-                    stdout.send(format_sstr!("Add {podcast_name} {podcast_url:?} {castid}"));
-                    Podcast::add_podcast(&pool, castid, podcast_name, podcast_url, &directory)
-                        .await?;
+                let castid = match castid {
+                    Some(c) => {
+                        if let Some(pod) = Podcast::from_index(&pool, c).await? {
+                            return Err(format_err!("castid {c} is taken by {}", pod.castname));
+                        }
+                        c
+                    }
+                    None => Podcast::get_max_castid(&pool).await?.map_or(1, |c| c + 1),
+                };
+                let directory = settings.directory.clone().unwrap_or_else(|| {
+                    let home_dir = std::env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
+                    format_sstr!("{home_dir}/{name}")
+                });
+                if let Some(template) = settings.filename_template.as_ref() {
+                    validate_template(template)?;
+                }
+                stdout.send(format_sstr!("Add {name} {url:?} {castid}"));
+                let mut pod = Podcast::add_podcast(&pool, castid, &name, &url, &directory).await?;
+                if settings.apply(&mut pod)? {
+                    pod.update_podcast(&pool).await?;
+                }
+                if let Some(keep_latest) = keep_latest {
+                    catch_up(&pool, &config, &stdout, &pod, keep_latest).await?;
                 }
             }
-        } else {
-            process_all_podcasts(&pool, &stdout).await?;
+            PodcatchCommand::Remove {
+                castid,
+                delete_files,
+                keep_history,
+                dry_run,
+                yes,
+            } => {
+                let mut pod = get_podcast(&pool, castid).await?;
+                let plan = pod.unsubscribe(&pool, delete_files, true).await?;
+                let summary = format_sstr!(
+                    "{} {} with {} episodes and {} files",
+                    pod.castid,
+                    pod.castname,
+                    plan.episodes,
+                    plan.files.len()
+                );
+                if keep_history {
+                    if dry_run {
+                        stdout.send(format_sstr!("would pause {summary}"));
+                    } else {
+                        pod.set_active(&pool, false).await?;
+                        stdout.send(format_sstr!("paused {summary}"));
+                    }
+                } else if dry_run {
+                    stdout.send(format_sstr!("would remove {summary}"));
+                    for file in &plan.files {
+                        stdout.send(format_sstr!("would delete {}", file.display()));
+                    }
+                } else if yes || confirm(&format_sstr!("Remove {summary}?"))? {
+                    let removed = pod.unsubscribe(&pool, delete_files, false).await?;
+                    for file in &removed.files {
+                        stdout.send(format_sstr!("deleted {}", file.display()));
+                    }
+                    stdout.send(format_sstr!(
+                        "removed {} {} and {} episodes",
+                        pod.castid,
+                        pod.castname,
+                        removed.episodes
+                    ));
+                } else {
+                    stdout.send(format_sstr!("kept {} {}", pod.castid, pod.castname));
+                }
+            }
+            PodcatchCommand::Pause { castid } => {
+                let mut pod = get_podcast(&pool, castid).await?;
+                let state = if pod.set_active(&pool, false).await? {
+                    "paused"
+                } else {
+                    "already paused"
+                };
+                stdout.send(format_sstr!("{state} {} {}", pod.castid, pod.castname));
+            }
+            PodcatchCommand::Resume { castid } => {
+                let mut pod = get_podcast(&pool, castid).await?;
+                let state = if pod.set_active(&pool, true).await? {
+                    "resumed"
+                } else {
+                    "already active"
+                };
+                stdout.send(format_sstr!("{state} {} {}", pod.castid, pod.castname));
+            }
+            PodcatchCommand::List { listing } => {
+                let podcasts: Vec<Podcast> = Podcast::get_all_podcasts(&pool, false)
+                    .await?
+                    .try_collect()
+                    .await?;
+                for line in listing.format(&podcasts)? {
+                    stdout.send(line);
+                }
+            }
+            PodcatchCommand::Episodes {
+                castid,
+                filter,
+                mut listing,
+            } => {
+                if let Some(castid) = castid {
+                    get_podcast(&pool, castid).await?;
+                }
+                // sorted by the database, `--limit` and `--offset` apply to
+                // the sorted episodes
+                let mut filter = filter.into_filter(castid);
+                filter.sort = listing.sort.take();
+                filter.reverse = std::mem::take(&mut listing.reverse);
+                let episodes = Episode::get_episodes(&pool, &filter).await?;
+                for line in listing.format(&episodes)? {
+                    stdout.send(line);
+                }
+            }
+            PodcatchCommand::Show { castid } => {
+                let pod = get_podcast(&pool, castid).await?;
+                for line in format_record(&pod) {
+                    stdout.send(line);
+                }
+                for feedurl in Podcast::get_feedurl_history(&pool, castid).await? {
+                    stdout.send(format_sstr!("previous feed url {feedurl}"));
+                }
+                let mut counts = BTreeMap::new();
+                for epi in Episode::get_all_episodes(&pool, castid).await? {
+                    *counts.entry(epi.status.to_str()).or_insert(0) += 1;
+                }
+                for (status, count) in counts {
+                    stdout.send(format_sstr!("{status} {count}"));
+                }
+            }
+            PodcatchCommand::Set {
+                castid,
+                name,
+                url,
+                settings,
+                episodeid,
+                status,
+            } => {
+                let mut pod = get_podcast(&pool, castid).await?;
+                let mut changed = settings.apply(&mut pod)?;
+                if let Some(name) = name {
+                    pod.castname = name;
+                    changed = true;
+                }
+                if let Some(url) = url.filter(|u| u.as_str() != pod.feedurl) {
+                    pod.add_feedurl_history(&pool, &pod.feedurl).await?;
+                    pod.feedurl = url.as_str().into();
+                    pod.etag = None;
+                    pod.last_modified = None;
+                    changed = true;
+                }
+                if changed {
+                    pod.update_podcast(&pool).await?;
+                    stdout.send(format_sstr!("updated {} {}", pod.castid, pod.castname));
+                }
+                if let Some((episodeid, status)) = episodeid.zip(status) {
+                    let mut epi = Episode::from_index(&pool, castid, episodeid)
+                        .await?
+                        .ok_or_else(|| format_err!("No episode {episodeid} in {castid}"))?;
+                    epi.set_status(status)?;
+                    epi.update_episode(&pool).await?;
+                    stdout.send(format_sstr!("episode {episodeid} {} {status}", epi.title));
+                }
+            }
+            PodcatchCommand::Requeue { castid } => {
+                let count = Episode::requeue_errors(&pool, castid).await?;
+                stdout.send(format_sstr!("requeued {count} episodes"));
+            }
+            PodcatchCommand::CatchUp {
+                castid,
+                keep_latest,
+            } => {
+                let pod = get_podcast(&pool, castid).await?;
+                catch_up(&pool, &config, &stdout, &pod, keep_latest).await?;
+            }
+            PodcatchCommand::Migrate => {
+                let mut conn = pool.get().await?;
+                migrations::runner().run_async(&mut **conn).await?;
+            }
+        }
+        if !failures.is_empty() {
+            stdout.send(format_sstr!("{} failures:", failures.len()));
+            for failure in &failures {
+                stdout.send(format_sstr!("    {failure}"));
+            }
+            stdout.close().await?;
+            return Err(format_err!("{} failures", failures.len()));
         }
         stdout.close().await.map_err(Into::into)
     }
 }
 
-async fn process_all_podcasts(
+/// Ask a yes/no question on the terminal, anything but `y` or `yes` is a no
+fn confirm(prompt: &str) -> Result<bool, Error> {
+    let mut stdout = io::stdout();
+    write!(stdout, "{prompt} [y/N] ")?;
+    stdout.flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Download the `Ready` episodes of a podcast, or of all podcasts, that
+/// haven't been downloaded yet.  A single episode is queued again first if
+/// a previous attempt failed or it was skipped.
+async fn download_queued(
     pool: &PgPool,
+    config: &Config,
     stdout: &StdoutChannel<StackString>,
+    castid: Option<i32>,
+    episodeid: Option<i32>,
+) -> Result<Vec<StackString>, Error> {
+    let recovered = Episode::recover_interrupted(pool, castid).await?;
+    if recovered > 0 {
+        stdout.send(format_sstr!("resume {recovered} interrupted downloads"));
+    }
+    let podcasts: Vec<Podcast> = match castid {
+        Some(castid) => vec![get_podcast(pool, castid).await?],
+        None => {
+            Podcast::get_all_podcasts(pool, true)
+                .await?
+                .try_collect()
+                .await?
+        }
+    };
+    let mut queue = Vec::new();
+    for pod in podcasts {
+        if pod.directory.is_none() {
+            continue;
+        }
+        let pod = Arc::new(pod);
+        let episodes = Episode::get_all_episodes(pool, pod.castid).await?;
+        let reserved = Arc::new(reserved_names(&episodes));
+        if let Some(episodeid) = episodeid {
+            let mut epi = Episode::from_index(pool, pod.castid, episodeid)
+                .await?
+                .ok_or_else(|| format_err!("No episode {episodeid} in {}", pod.castid))?;
+            if epi.status != EpisodeStatus::Ready
+                && epi.status.can_transition_to(EpisodeStatus::Ready)
+            {
+                epi.set_status(EpisodeStatus::Ready)?;
+            }
+            queue.push((pod.clone(), epi, reserved));
+        } else {
+            for epi in episodes {
+                if epi.status == EpisodeStatus::Ready && epi.checksum.is_none() {
+                    queue.push((pod.clone(), epi, reserved.clone()));
+                }
+            }
+        }
+    }
+    let pod_conn = PodConnection::from_config(config);
+    let titles: Vec<_> = queue
+        .iter()
+        .map(|(pod, epi, _)| format_sstr!("{} {}", pod.castname, epi.title))
+        .collect();
+    let results: Vec<Result<_, Error>> = run_queue(
+        queue,
+        config.max_concurrent_requests(),
+        |(pod, epi, reserved)| {
+            let pod_conn = pod_conn.clone();
+            async move {
+                let directory = pod.directory.as_ref().map_or("", StackString::as_str);
+                let (epi, extras) = fetch_episode(
+                    pool,
+                    &pod_conn,
+                    Path::new(directory),
+                    &epi,
+                    &reserved,
+                    config,
+                )
+                .await?;
+                let mut output = vec![format_sstr!(
+                    "download {} {directory} {}",
+                    epi.epurl,
+                    epi.file_name()?
+                )];
+                output.extend(extras);
+                Ok(StackString::from(output.join("\n")))
+            }
+        },
+    )
+    .await;
+    let mut failures = Vec::new();
+    for (title, result) in titles.into_iter().zip(results) {
+        match result {
+            Ok(line) => stdout.send(line),
+            Err(e) => failures.push(format_sstr!("{title}: {e}")),
+        }
+    }
+    Ok(failures)
+}
+
+/// File names of the episodes of a podcast, to keep downloads from taking
+/// the name of another episode
+fn reserved_names(episodes: &[Episode]) -> ReservedNames {
+    let names = episodes.iter().filter_map(|e| e.filename.clone()).collect();
+    ReservedNames::new(names)
+}
+
+/// Download an episode that is already in the database, it is marked
+/// `Downloading` first so an interrupted download is picked up by the next
+/// run.  With the `download_extras` config setting the chapters and
+/// transcripts stored for it are downloaded as well, returns the episode and
+/// a line for each extra file.  Failing to get the extras doesn't fail the
+/// episode.
+async fn fetch_episode(
+    pool: &PgPool,
+    pod_conn: &PodConnection,
+    directory: &Path,
+    epi: &Episode,
+    reserved: &ReservedNames,
+    config: &Config,
+) -> Result<(Episode, Vec<StackString>), Error> {
+    let mut epi = epi.clone();
+    epi.set_status(EpisodeStatus::Downloading)?;
+    epi.update_episode(pool).await?;
+    let epi = epi
+        .download_episode(
+            pod_conn,
+            directory,
+            reserved,
+            config.max_download_attempts(),
+        )
+        .await?;
+    epi.update_episode(pool).await?;
+    if epi.status == EpisodeStatus::Error {
+        return Err(download_error(&epi));
+    }
+    if !config.download_extras {
+        return Ok((epi, Vec::new()));
+    }
+    let extras = download_stored_extras(pool, pod_conn, directory, &epi).await;
+    Ok((epi, extras_output(extras)))
+}
+
+/// Download the chapters and transcripts stored for an episode
+async fn download_stored_extras(
+    pool: &PgPool,
+    pod_conn: &PodConnection,
+    directory: &Path,
+    epi: &Episode,
+) -> Result<Vec<StackString>, Error> {
+    let chapters = EpisodeChapters::get_by_episode(pool, epi.castid, epi.episodeid).await?;
+    let transcripts = EpisodeTranscript::get_by_episode(pool, epi.castid, epi.episodeid).await?;
+    download_episode_extras(
+        pod_conn,
+        directory,
+        &episode_stem(epi)?,
+        chapters.as_ref(),
+        &transcripts,
+    )
+    .await
+}
+
+/// Lines reporting the extra files of an episode, or why they couldn't be
+/// downloaded
+fn extras_output(extras: Result<Vec<StackString>, Error>) -> Vec<StackString> {
+    match extras {
+        Ok(files) => files
+            .iter()
+            .map(|f| format_sstr!("extra download {f}"))
+            .collect(),
+        Err(e) => vec![format_sstr!("extra download failed: {e}")],
+    }
+}
+
+/// File name of the episode without its extension, the chapters and
+/// transcripts are stored under it
+fn episode_stem(epi: &Episode) -> Result<StackString, Error> {
+    let basename = epi.file_name()?;
+    Ok(Path::new(basename.as_str())
+        .file_stem()
+        .map_or_else(|| basename.clone(), |s| s.to_string_lossy().into()))
+}
+
+async fn catch_up(
+    pool: &PgPool,
+    config: &Config,
+    stdout: &StdoutChannel<StackString>,
+    pod: &Podcast,
+    keep_latest: usize,
 ) -> Result<(), Error> {
-    let pod_conn = PodConnection::new();
-    let podcasts: Vec<_> = Podcast::get_all_podcasts(pool).await?.try_collect().await?;
-    let futures = podcasts.into_iter().map(|pod| {
+    let pod_conn = PodConnection::from_config(config);
+    let (ready, skipped) = pod.catch_up(pool, &pod_conn, keep_latest).await?;
+    stdout.send(format_sstr!(
+        "catch up {} {ready} ready {skipped} skipped",
+        pod.castname
+    ));
+    Ok(())
+}
+
+/// Store the `podcast:` namespace metadata of a newly inserted episode and
+/// optionally download its chapters and transcripts
+async fn process_episode_extras(
+    pool: &PgPool,
+    download_extras: bool,
+    pod_conn: &PodConnection,
+    feed: &Feed,
+    epi: &Episode,
+    directory: &Path,
+) -> Result<Vec<StackString>, Error> {
+    let Some(item) = feed
+        .items
+        .iter()
+        .find(|item| item.enclosure.as_ref().is_some_and(|e| e.url == epi.epurl))
+    else {
+        return Ok(Vec::new());
+    };
+    store_episode_metadata(pool, epi.castid, epi.episodeid, &item.podcast).await?;
+    if !download_extras {
+        return Ok(Vec::new());
+    }
+    let stem = episode_stem(epi)?;
+    let chapters = item
+        .podcast
+        .chapters
+        .as_ref()
+        .map(|c| EpisodeChapters::from_feed(epi.castid, epi.episodeid, c));
+    let transcripts: Vec<_> = item
+        .podcast
+        .transcripts
+        .iter()
+        .map(|t| EpisodeTranscript::from_feed(epi.castid, epi.episodeid, t))
+        .collect();
+    download_episode_extras(pod_conn, directory, &stem, chapters.as_ref(), &transcripts).await
+}
+
+/// Refresh the given podcast, or all active podcasts, and download their new
+/// episodes.  Without `download` only the metadata is refreshed, new episodes
+/// are recorded as `Ready` for a later run.  Episodes still waiting for their
+/// download are fetched even when the feed is not modified, the retention
+/// policy is applied to every podcast.  A broken feed or a failed
+/// download doesn't stop the other podcasts and episodes from being
+/// processed, the failures are returned instead.
+async fn process_all_podcasts(
+    pool: &PgPool,
+    config: &Config,
+    stdout: &StdoutChannel<StackString>,
+    podcast: Option<Podcast>,
+    download: bool,
+) -> Result<Vec<StackString>, Error> {
+    let mut failures = Vec::new();
+    let castid = podcast.as_ref().map(|p| p.castid);
+    let recovered = Episode::recover_interrupted(pool, castid).await?;
+    if recovered > 0 {
+        stdout.send(format_sstr!("resume {recovered} interrupted downloads"));
+    }
+    let pod_conn = PodConnection::from_config(config);
+    let workers = config.max_concurrent_requests();
+    let podcasts: Vec<_> = match podcast {
+        Some(pod) => vec![pod],
+        None => {
+            Podcast::get_all_podcasts(pool, true)
+                .await?
+                .try_collect()
+                .await?
+        }
+    };
+    let results: Vec<Result<_, Error>> = run_queue(podcasts.clone(), workers, |mut pod| {
         let pool = pool.clone();
         let pod_conn = pod_conn.clone();
-        let pod = Arc::new(pod);
         async move {
             let episodes = Episode::get_all_episodes(&pool, pod.castid).await?;
             let max_epid = Episode::get_max_epid(&pool).await?;
 
-            let episode_map: Result<HashSet<Episode>, Error> =
-                episodes.into_iter().map(Ok).collect();
+            let episode_map: EpisodeIndex = episodes.into_iter().collect();
 
-            let episode_map = episode_map?;
+            let feedurl = pod.feedurl.clone();
+            let etag = pod.etag.clone();
+            let last_modified = pod.last_modified.clone();
+            let feed = pod_conn.get_feed(&mut pod).await?;
+            if let Some(feed) = feed.as_ref() {
+                let history = Podcast::get_feedurl_history(&pool, pod.castid).await?;
+                pod.follow_new_feed_url(&feed.channel, &history);
+            }
+            let moved = pod.feedurl != feedurl;
+            if moved {
+                stdout.send(format_sstr!(
+                    "podcast {} moved from {feedurl} to {}",
+                    pod.castname,
+                    pod.feedurl
+                ));
+                pod.add_feedurl_history(&pool, &feedurl).await?;
+            }
+            let Some(feed) = feed else {
+                if moved {
+                    pod.update_podcast(&pool).await?;
+                }
+                stdout.send(format_sstr!("podcast {} not modified", pod.castname));
+                return Ok(None);
+            };
+            // the new cache headers are only stored once the episodes of the
+            // feed have been recorded, until then the old ones are kept so a
+            // failed run fetches the whole feed again.  After a move the old
+            // ones belong to the old feed url and are dropped, after
+            // following `itunes:new-feed-url` there are no new ones either.
+            let cache = (pod.etag.take(), pod.last_modified.take());
+            if !moved {
+                pod.etag = etag;
+                pod.last_modified = last_modified;
+            }
+            if pod.update_metadata(&feed.channel) || moved {
+                pod.update_podcast(&pool).await?;
+            }
+            store_podcast_metadata(&pool, pod.castid, &feed.channel.podcast).await?;
+            let pod = Arc::new(pod);
 
-            let episode_list = pod_conn
-                .parse_feed(&pod, &episode_map, max_epid + 1)
-                .await?;
+            let episode_list = PodConnection::get_episodes(&pod, &feed, &episode_map, max_epid + 1);
             let episode_list = Arc::new(episode_list);
+            let feed = Arc::new(feed);
 
-            Ok((pod, episode_list, max_epid, episode_map))
+            Ok(Some((
+                pod,
+                feed,
+                episode_list,
+                max_epid,
+                episode_map,
+                cache,
+            )))
         }
-    });
-    let results: Result<Vec<_>, Error> = try_join_all(futures).await;
+    })
+    .await;
 
-    for (pod, episode_list, max_epid, episode_map) in results? {
-        let new_episodes: Vec<_> = episode_list
-            .iter()
-            .filter(|e| e.status == EpisodeStatus::Ready)
-            .collect();
-        let update_episodes: Vec<_> = episode_list
-            .iter()
-            .filter(|e| e.status != EpisodeStatus::Ready && e.status != EpisodeStatus::Downloaded)
-            .collect();
+    for (pod, result) in podcasts.into_iter().zip(results) {
+        // episodes downloaded while recording the feed aren't tried again
+        // in the same run
+        let mut tried = HashSet::new();
+        let pod = match result {
+            Ok(Some((pod, feed, episode_list, max_epid, episode_map, cache))) => {
+                let failed = failures.len();
+                tried = record_feed_episodes(
+                    pool,
+                    config,
+                    stdout,
+                    &pod_conn,
+                    &pod,
+                    &feed,
+                    &episode_list,
+                    max_epid,
+                    &episode_map,
+                    download,
+                    &mut failures,
+                )
+                .await;
+                let mut pod = (*pod).clone();
+                let (etag, last_modified) = cache;
+                if failures.len() == failed
+                    && (pod.etag != etag || pod.last_modified != last_modified)
+                {
+                    pod.etag = etag;
+                    pod.last_modified = last_modified;
+                    if let Err(e) = pod.update_podcast(pool).await {
+                        failures.push(format_sstr!("{}: {e}", pod.castname));
+                    }
+                }
+                pod
+            }
+            Ok(None) => pod,
+            Err(e) => {
+                failures.push(format_sstr!("{}: {e}", pod.castname));
+                pod
+            }
+        };
+        // whether or not the feed changed, or could be fetched at all,
+        // episodes still waiting for their download are fetched and the
+        // retention policy is applied
+        if download {
+            match download_pending(pool, config, &pod_conn, stdout, &pod, &tried).await {
+                Ok(errors) => failures.extend(errors),
+                Err(e) => failures.push(format_sstr!("{}: {e}", pod.castname)),
+            }
+        }
+        match cleanup_podcast(pool, &pod).await {
+            Ok(lines) => {
+                for line in lines {
+                    stdout.send(line);
+                }
+            }
+            Err(e) => failures.push(format_sstr!("{} cleanup: {e}", pod.castname)),
+        }
+    }
+    Ok(failures)
+}
 
-        stdout.send(format_sstr!(
-            "podcast {} {} {} {} {}",
-            pod.castname,
-            max_epid,
-            episode_map.len(),
-            new_episodes.len(),
-            update_episodes.len(),
-        ));
+/// Record the new episodes of a refreshed feed, downloading them if
+/// `download` is set, and update the metadata of the known ones.  Returns
+/// the ids of the episodes whose download was attempted.
+#[allow(clippy::too_many_arguments)]
+async fn record_feed_episodes(
+    pool: &PgPool,
+    config: &Config,
+    stdout: &StdoutChannel<StackString>,
+    pod_conn: &PodConnection,
+    pod: &Arc<Podcast>,
+    feed: &Arc<Feed>,
+    episode_list: &[(Episode, Option<EpisodeMatch>)],
+    max_epid: i32,
+    episode_map: &EpisodeIndex,
+    download: bool,
+    failures: &mut Vec<StackString>,
+) -> HashSet<i32> {
+    let workers = config.max_concurrent_requests();
+    // file names are picked up front so episodes downloading at the same
+    // time can't end up with the same name
+    let mut taken: HashSet<_> = episode_map
+        .episodes()
+        .iter()
+        .filter_map(|e| e.filename.clone())
+        .collect();
+    let mut new_episodes = Vec::new();
+    for (epi, _) in episode_list.iter().filter(|(_, reason)| reason.is_none()) {
+        let mut epi = epi.clone();
+        if let Some(directory) = pod.directory.as_ref() {
+            let template = pod.filename_template.as_ref().map(StackString::as_str);
+            match render_filename(template, &epi) {
+                Ok(filename) => {
+                    let directory = Path::new(directory.as_str());
+                    epi.filename = Some(unique_filename(&filename, directory, &mut taken));
+                }
+                Err(e) => {
+                    failures.push(format_sstr!("{} {}: {e}", pod.castname, epi.title));
+                    continue;
+                }
+            }
+        }
+        new_episodes.push(epi);
+    }
+    let update_episodes: Vec<_> = episode_list
+        .iter()
+        .filter_map(|(e, reason)| reason.map(|r| (e, r)))
+        .collect();
 
-        let futures = new_episodes.into_iter().map(|epi| {
-            let pod = pod.clone();
-            let pod_conn = pod_conn.clone();
-            async move {
-                if let Some(directory) = pod.directory.as_ref() {
-                    let directory_path = Path::new(directory.as_str());
-                    let mut output = vec![format_sstr!(
-                        "new download {} {} {}",
-                        epi.epurl,
-                        directory,
-                        epi.url_basename()?
-                    )];
-                    if let Some(mut new_epi) =
-                        Episode::from_epurl(pool, pod.castid, &epi.epurl).await?
-                    {
-                        output.push(format_sstr!("new title {}", epi.title));
-                        new_epi.title = epi.title.clone();
-                        new_epi.update_episode(pool).await?;
-                    } else {
-                        let new_epi = epi.download_episode(&pod_conn, directory_path).await?;
-                        if new_epi.epguid.is_some() {
-                            new_epi.insert_episode(pool).await?;
-                        } else {
-                            output.push(format_sstr!("No md5sum? {new_epi:?}"));
+    stdout.send(format_sstr!(
+        "podcast {} {} {} {} {}",
+        pod.castname,
+        max_epid,
+        episode_map.len(),
+        new_episodes.len(),
+        update_episodes.len(),
+    ));
+
+    if download {
+        // episodes the retention policy would delete right away, like most of
+        // the back catalogue of a new podcast, are skipped
+        match expired_pending(pod, episode_map.episodes(), &new_episodes).await {
+            Ok(expired) => {
+                for epi in &mut new_episodes {
+                    if expired.contains(&epi.episodeid) {
+                        if let Err(e) = epi.set_status(EpisodeStatus::Skipped) {
+                            failures.push(format_sstr!("{} {}: {e}", pod.castname, epi.title));
                         }
                     }
-                    Ok(Some(output))
-                } else {
-                    Ok(None)
                 }
             }
-        });
-        let results: Result<Vec<_>, Error> = try_join_all(futures).await;
-        for line in results?.into_iter().flatten() {
-            stdout.send(line.join("\n"));
+            Err(e) => failures.push(format_sstr!("{} retention: {e}", pod.castname)),
         }
+    }
 
-        let futures = update_episodes.into_iter().map(|epi| {
-            let pod = pod.clone();
-            let pod_conn = pod_conn.clone();
-            async move {
-                let mut output = Vec::new();
-                let url = epi.url_basename()?;
-                let epguid = epi
-                    .epguid
-                    .as_ref()
-                    .ok_or_else(|| format_err!("no md5sum"))?;
-                if let Some(directory) = pod.directory.as_ref() {
-                    let directory_path = Path::new(directory.as_str());
-                    if epguid.len() != 32 {
-                        let path = directory_path.join(url.as_str());
-                        let fname = path.to_string_lossy();
-                        if path.exists() {
-                            if let Ok(md5sum) = get_md5sum(&path) {
-                                let mut p = epi.clone();
-                                output.push(format_sstr!("update md5sum {fname} {md5sum}"));
-                                p.epguid = Some(md5sum);
-                                p.update_episode(pool).await?;
-                            }
-                        } else if let Ok(url_) = epi.epurl.parse::<Url>() {
-                            output.push(format_sstr!("download {url_:?} {fname}"));
-                            let new_epi = epi.download_episode(&pod_conn, directory_path).await?;
-                            new_epi.update_episode(pool).await?;
-                        }
+    let reserved = ReservedNames::new(taken);
+    let reserved = &reserved;
+    let results: Vec<Result<_, Error>> = run_queue(new_episodes.clone(), workers, |epi| {
+        let pod = pod.clone();
+        let feed = feed.clone();
+        let pod_conn = pod_conn.clone();
+        async move {
+            if let Some(directory) = pod.directory.as_ref() {
+                let directory_path = Path::new(directory.as_str());
+                let action = if epi.status == EpisodeStatus::Skipped {
+                    "new skipped"
+                } else if download {
+                    "new download"
+                } else {
+                    "new episode"
+                };
+                let mut output = vec![format_sstr!(
+                    "{action} {} {} {}",
+                    epi.epurl,
+                    directory,
+                    epi.file_name()?
+                )];
+                if let Some(mut new_epi) = Episode::from_epurl(pool, pod.castid, &epi.epurl).await?
+                {
+                    output.push(format_sstr!("new title {}", epi.title));
+                    new_epi.title = epi.title.clone();
+                    if new_epi.guid.is_none() {
+                        new_epi.guid.clone_from(&epi.guid);
+                    }
+                    new_epi.update_episode(pool).await?;
+                } else if !download || epi.status == EpisodeStatus::Skipped {
+                    epi.insert_episode(pool).await?;
+                    process_episode_extras(pool, false, &pod_conn, &feed, &epi, directory_path)
+                        .await?;
+                } else {
+                    // the episode is recorded before the download starts so
+                    // an interrupted download is picked up by the next run
+                    let mut new_epi = epi.clone();
+                    new_epi.set_status(EpisodeStatus::Downloading)?;
+                    new_epi.insert_episode(pool).await?;
+                    let new_epi = new_epi
+                        .download_episode(
+                            &pod_conn,
+                            directory_path,
+                            reserved,
+                            config.max_download_attempts(),
+                        )
+                        .await?;
+                    new_epi.update_episode(pool).await?;
+                    if new_epi.status == EpisodeStatus::Error {
+                        return Err(download_error(&new_epi));
+                    } else if new_epi.checksum.is_some() {
+                        let extras = process_episode_extras(
+                            pool,
+                            config.download_extras,
+                            &pod_conn,
+                            &feed,
+                            &new_epi,
+                            directory_path,
+                        )
+                        .await;
+                        output.extend(extras_output(extras));
+                    } else {
+                        output.push(format_sstr!("No md5sum? {new_epi:?}"));
                     }
                 }
-                Ok(output)
+                Ok(Some(output))
+            } else {
+                Ok(None)
             }
-        });
-        let results: Result<Vec<_>, Error> = try_join_all(futures).await;
-        for line in results? {
-            stdout.send(line.join("\n"));
+        }
+    })
+    .await;
+    let tried = if download {
+        new_episodes.iter().map(|epi| epi.episodeid).collect()
+    } else {
+        HashSet::new()
+    };
+    for (epi, result) in new_episodes.into_iter().zip(results) {
+        match result {
+            Ok(Some(line)) => stdout.send(line.join("\n")),
+            Ok(None) => {}
+            Err(e) => failures.push(format_sstr!("{} {}: {e}", pod.castname, epi.title)),
         }
     }
-    Ok(())
+
+    // only the metadata of known episodes is updated here, the ones still
+    // waiting for their download are fetched by `download_pending`
+    for (epi, reason) in update_episodes {
+        stdout.send(format_sstr!(
+            "update {} {} matched by {reason}",
+            epi.episodeid,
+            epi.title
+        ));
+        if let Err(e) = epi.update_episode(pool).await {
+            failures.push(format_sstr!("{} {}: {e}", pod.castname, epi.title));
+        }
+    }
+
+    // the `podcast:` namespace metadata isn't part of the episode row, so
+    // it is stored for every known episode the feed has some for
+    for item in feed.items.iter().filter(|item| !item.podcast.is_empty()) {
+        let Some(enclosure) = item.enclosure.as_ref() else {
+            continue;
+        };
+        let title = item.title.as_ref().map_or("Unknown", StackString::as_str);
+        let guid = item.guid.as_ref().map(StackString::as_str);
+        if let Some((epi, _)) = episode_map.find(guid, &enclosure.url, title) {
+            if let Err(e) =
+                store_episode_metadata(pool, epi.castid, epi.episodeid, &item.podcast).await
+            {
+                failures.push(format_sstr!("{} {}: {e}", pod.castname, epi.title));
+            }
+        }
+    }
+    tried
+}
+
+/// Download the episodes of a podcast still waiting for it: re-queued
+/// failures, interrupted downloads and episodes recorded by a catch-up or a
+/// refresh without downloads.  Failed downloads are retried until they used
+/// up `max_download_attempts`, one attempt per run: episodes in `tried` are
+/// left alone.  Episodes the retention policy would delete right after the
+/// download are marked `Skipped` instead.  A file already in place only gets
+/// its md5sum recorded.
+/// Returns the failed downloads.
+async fn download_pending(
+    pool: &PgPool,
+    config: &Config,
+    pod_conn: &PodConnection,
+    stdout: &StdoutChannel<StackString>,
+    pod: &Podcast,
+    tried: &HashSet<i32>,
+) -> Result<Vec<StackString>, Error> {
+    let Some(directory) = pod.directory.as_ref() else {
+        return Ok(Vec::new());
+    };
+    let directory = Path::new(directory.as_str());
+    let episodes = Episode::get_all_episodes(pool, pod.castid).await?;
+    let reserved = reserved_names(&episodes);
+    let reserved = &reserved;
+    let max_attempts = config.max_download_attempts();
+    let (unchecked, to_fetch): (Vec<_>, Vec<_>) = episodes
+        .iter()
+        .filter(|epi| !tried.contains(&epi.episodeid))
+        .filter(|epi| match epi.status {
+            EpisodeStatus::Ready | EpisodeStatus::Downloaded => epi.checksum.is_none(),
+            EpisodeStatus::Error => epi.attempts < max_attempts,
+            _ => false,
+        })
+        .cloned()
+        .partition(|epi| epi.status == EpisodeStatus::Downloaded);
+    // episodes the retention policy would delete right away aren't fetched
+    let expired = expired_pending(pod, &episodes, &to_fetch).await?;
+    let mut pending = unchecked;
+    for mut epi in to_fetch {
+        if expired.contains(&epi.episodeid) {
+            epi.set_status(EpisodeStatus::Skipped)?;
+            epi.update_episode(pool).await?;
+            stdout.send(format_sstr!("skip {} {}", epi.episodeid, epi.title));
+        } else {
+            pending.push(epi);
+        }
+    }
+    let titles: Vec<_> = pending.iter().map(|epi| epi.title.clone()).collect();
+    let workers = config.max_concurrent_requests();
+    let results: Vec<Result<_, Error>> = run_queue(pending, workers, |mut epi| {
+        let pod_conn = pod_conn.clone();
+        async move {
+            if epi.status == EpisodeStatus::Error {
+                epi.set_status(EpisodeStatus::Ready)?;
+            }
+            let path = directory.join(epi.file_name()?.as_str());
+            let fname = path.to_string_lossy();
+            // downloads are only renamed into place once complete, an
+            // interrupted one is still sitting at `<path>.part`
+            if path.exists() {
+                let md5sum = get_md5sum(&path)?;
+                let line = format_sstr!("update md5sum {fname} {md5sum}");
+                epi.checksum = Some(md5sum);
+                epi.set_status(EpisodeStatus::Downloaded)?;
+                epi.update_episode(pool).await?;
+                Ok(line)
+            } else {
+                let mut output = vec![format_sstr!("download {} {fname}", epi.epurl)];
+                let (_, extras) =
+                    fetch_episode(pool, &pod_conn, directory, &epi, reserved, config).await?;
+                output.extend(extras);
+                Ok(StackString::from(output.join("\n")))
+            }
+        }
+    })
+    .await;
+    let mut failures = Vec::new();
+    for (title, result) in titles.into_iter().zip(results) {
+        match result {
+            Ok(line) => stdout.send(line),
+            Err(e) => failures.push(format_sstr!("{} {title}: {e}", pod.castname)),
+        }
+    }
+    Ok(failures)
+}
+
+fn download_error(epi: &Episode) -> Error {
+    format_err!(
+        "download of {} failed after {} attempts: {}",
+        epi.epurl,
+        epi.attempts,
+        epi.error_message.as_ref().map_or("", StackString::as_str)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{CommandFactory, Parser};
+    use time::macros::datetime;
+
+    use crate::{
+        episode_status::EpisodeStatus,
+        podcatch_opts::{PodcatchCommand, PodcatchOpts},
+    };
+
+    #[test]
+    fn test_podcatch_opts() {
+        PodcatchOpts::command().debug_assert();
+        let opts = PodcatchOpts::try_parse_from(["podcatch-rust"]).unwrap();
+        assert!(opts.command.is_none());
+        let opts =
+            PodcatchOpts::try_parse_from(["podcatch-rust", "refresh", "-p", "19", "--no-download"])
+                .unwrap();
+        assert!(matches!(
+            opts.command,
+            Some(PodcatchCommand::Refresh {
+                podcast: Some(_),
+                no_download: true
+            })
+        ));
+        assert!(PodcatchOpts::try_parse_from(["podcatch-rust", "add", "-n", "test"]).is_err());
+        assert!(PodcatchOpts::try_parse_from(["podcatch-rust", "download", "-e", "1"]).is_err());
+        assert!(
+            PodcatchOpts::try_parse_from(["podcatch-rust", "set", "-i", "1", "-e", "2"]).is_err()
+        );
+        let opts = PodcatchOpts::try_parse_from([
+            "podcatch-rust",
+            "set",
+            "-i",
+            "1",
+            "-e",
+            "2",
+            "-s",
+            "played",
+            "--keep-latest",
+            "5",
+        ])
+        .unwrap();
+        assert!(matches!(
+            opts.command,
+            Some(PodcatchCommand::Set {
+                castid: 1,
+                episodeid: Some(2),
+                ..
+            })
+        ));
+        let opts = PodcatchOpts::try_parse_from([
+            "podcatch-rust",
+            "episodes",
+            "-s",
+            "ready,Error",
+            "--since",
+            "2024-01-31",
+            "--limit",
+            "5",
+        ])
+        .unwrap();
+        let Some(PodcatchCommand::Episodes { castid, filter, .. }) = opts.command else {
+            panic!("expected episodes");
+        };
+        let filter = filter.into_filter(castid);
+        assert_eq!(filter.status, [EpisodeStatus::Ready, EpisodeStatus::Error]);
+        assert_eq!(filter.since, Some(datetime!(2024-01-31 0:00 UTC)));
+        assert_eq!(filter.limit, Some(5));
+        assert!(PodcatchOpts::try_parse_from([
+            "podcatch-rust",
+            "episodes",
+            "--since",
+            "yesterday"
+        ])
+        .is_err());
+        assert!(PodcatchOpts::try_parse_from([
+            "podcatch-rust",
+            "set",
+            "-i",
+            "1",
+            "--keep-latest",
+            "-1"
+        ])
+        .is_err());
+    }
 }
@@ -0,0 +1,289 @@
+use anyhow::Error;
+use stack_string::{format_sstr, StackString};
+use std::{collections::HashSet, convert::TryFrom, io::ErrorKind, path::Path, slice};
+use time::{Duration, OffsetDateTime};
+use tokio::fs;
+
+use crate::{episode::Episode, episode_status::EpisodeStatus, pgpool::PgPool, podcast::Podcast};
+
+/// How many downloaded episodes of a podcast are kept on disk
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub keep_latest: Option<usize>,
+    pub max_age_days: Option<i64>,
+    pub max_total_bytes: Option<u64>,
+}
+
+impl RetentionPolicy {
+    /// Retention settings of a podcast, `None` if it keeps everything.  A
+    /// negative limit is ignored rather than expiring every episode.
+    #[must_use]
+    pub fn from_podcast(podcast: &Podcast) -> Option<Self> {
+        let policy = Self {
+            keep_latest: podcast.keep_latest.and_then(|n| usize::try_from(n).ok()),
+            max_age_days: podcast.max_age_days.filter(|n| *n >= 0).map(i64::from),
+            max_total_bytes: podcast.max_total_bytes.and_then(|n| u64::try_from(n).ok()),
+        };
+        if policy == Self::default() {
+            None
+        } else {
+            Some(policy)
+        }
+    }
+
+    /// Pick the episodes to remove from `(episode, file size)` pairs: the
+    /// newest episodes are kept until one of the limits is reached.  Episodes
+    /// without a publication date count as the oldest but never expire by
+    /// age, nor does anything when the age limit reaches back further than
+    /// dates go.
+    #[must_use]
+    pub fn expired<'a>(
+        &self,
+        episodes: &[(&'a Episode, u64)],
+        now: OffsetDateTime,
+    ) -> Vec<&'a Episode> {
+        let mut episodes = episodes.to_vec();
+        episodes.sort_by(|(a, _), (b, _)| {
+            b.pubdate
+                .is_some()
+                .cmp(&a.pubdate.is_some())
+                .then(b.pubdate.cmp(&a.pubdate))
+                .then(b.episodeid.cmp(&a.episodeid))
+        });
+        let cutoff = self
+            .max_age_days
+            .and_then(|days| now.checked_sub(Duration::days(days)));
+        let mut total_bytes = 0;
+        let mut expired = Vec::new();
+        for (idx, (epi, size)) in episodes.into_iter().enumerate() {
+            total_bytes += size;
+            let too_many = self.keep_latest.is_some_and(|n| idx >= n);
+            let too_old = cutoff
+                .zip(epi.pubdate)
+                .is_some_and(|(cutoff, pubdate)| pubdate < cutoff);
+            let too_big = self.max_total_bytes.is_some_and(|n| total_bytes > n);
+            if too_many || too_old || too_big {
+                expired.push(epi);
+            }
+        }
+        expired
+    }
+
+    /// Ids of the `pending` episodes, sized by their enclosure length, that
+    /// would expire as soon as they are downloaded next to `downloaded`
+    #[must_use]
+    pub fn expired_pending(
+        &self,
+        downloaded: &[(&Episode, u64)],
+        pending: &[Episode],
+        now: OffsetDateTime,
+    ) -> HashSet<i32> {
+        let pending_ids: HashSet<_> = pending.iter().map(|e| e.episodeid).collect();
+        let mut episodes = downloaded.to_vec();
+        episodes.extend(pending.iter().map(|e| {
+            let size = e.enclength.and_then(|l| u64::try_from(l).ok());
+            (e, size.unwrap_or(0))
+        }));
+        self.expired(&episodes, now)
+            .into_iter()
+            .map(|e| e.episodeid)
+            .filter(|id| pending_ids.contains(id))
+            .collect()
+    }
+}
+
+/// The downloaded episodes of `episodes` with the size of their file
+async fn downloaded_sizes<'a>(
+    directory: &Path,
+    episodes: &'a [Episode],
+) -> Result<Vec<(&'a Episode, u64)>, Error> {
+    let mut downloaded = Vec::new();
+    for epi in episodes {
+        if !matches!(
+            epi.status,
+            EpisodeStatus::Downloaded | EpisodeStatus::Played
+        ) {
+            continue;
+        }
+        let path = directory.join(epi.file_name()?.as_str());
+        let size = match fs::metadata(&path).await {
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        };
+        downloaded.push((epi, size));
+    }
+    Ok(downloaded)
+}
+
+/// Ids of the `pending` episodes of the podcast that its retention policy
+/// would delete right after downloading them, given its other `episodes`
+/// # Errors
+/// Return error if parsing an episode url fails
+pub async fn expired_pending(
+    podcast: &Podcast,
+    episodes: &[Episode],
+    pending: &[Episode],
+) -> Result<HashSet<i32>, Error> {
+    let (Some(policy), Some(directory)) = (
+        RetentionPolicy::from_podcast(podcast),
+        podcast.directory.as_ref(),
+    ) else {
+        return Ok(HashSet::new());
+    };
+    let downloaded = downloaded_sizes(Path::new(directory.as_str()), episodes).await?;
+    Ok(policy.expired_pending(&downloaded, pending, OffsetDateTime::now_utc()))
+}
+
+/// Delete the files of the episodes falling outside the retention policy of
+/// the podcast, including their chapters, transcripts and unfinished
+/// downloads, and mark them `Deleted` so they aren't downloaded again
+/// # Errors
+/// Return error if db query fails or a file can't be removed
+pub async fn cleanup_podcast(pool: &PgPool, podcast: &Podcast) -> Result<Vec<StackString>, Error> {
+    let mut output = Vec::new();
+    let (Some(policy), Some(directory)) = (
+        RetentionPolicy::from_podcast(podcast),
+        podcast.directory.as_ref(),
+    ) else {
+        return Ok(output);
+    };
+    let directory = Path::new(directory.as_str());
+    let episodes = Episode::get_all_episodes(pool, podcast.castid).await?;
+    let downloaded = downloaded_sizes(directory, &episodes).await?;
+    for epi in policy.expired(&downloaded, OffsetDateTime::now_utc()) {
+        for path in podcast.episode_files(pool, slice::from_ref(epi)).await? {
+            match fs::remove_file(&path).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+            output.push(format_sstr!("delete {} {}", epi.episodeid, path.display()));
+        }
+        let mut p = epi.clone();
+        p.set_status(EpisodeStatus::Deleted)?;
+        p.update_episode(pool).await?;
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::datetime;
+
+    use crate::{episode::Episode, podcast::Podcast, retention::RetentionPolicy};
+
+    fn episodes() -> Vec<Episode> {
+        vec![
+            Episode {
+                episodeid: 1,
+                pubdate: Some(datetime!(2024-01-01 0:00 UTC)),
+                ..Episode::default()
+            },
+            Episode {
+                episodeid: 2,
+                pubdate: Some(datetime!(2024-02-01 0:00 UTC)),
+                ..Episode::default()
+            },
+            Episode {
+                episodeid: 3,
+                pubdate: Some(datetime!(2024-03-01 0:00 UTC)),
+                ..Episode::default()
+            },
+            Episode {
+                episodeid: 4,
+                pubdate: None,
+                ..Episode::default()
+            },
+        ]
+    }
+
+    fn expired(policy: RetentionPolicy) -> Vec<i32> {
+        let episodes = episodes();
+        let sized: Vec<_> = episodes.iter().map(|e| (e, 100)).collect();
+        let mut ids: Vec<_> = policy
+            .expired(&sized, datetime!(2024-03-10 0:00 UTC))
+            .into_iter()
+            .map(|e| e.episodeid)
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    #[test]
+    fn test_retention_expired() {
+        assert!(expired(RetentionPolicy::default()).is_empty());
+        let keep_latest = RetentionPolicy {
+            keep_latest: Some(2),
+            ..RetentionPolicy::default()
+        };
+        assert_eq!(expired(keep_latest), vec![1, 4]);
+        let max_age = RetentionPolicy {
+            max_age_days: Some(45),
+            ..RetentionPolicy::default()
+        };
+        assert_eq!(expired(max_age), vec![1]);
+        let huge_max_age = RetentionPolicy {
+            max_age_days: Some(4_000_000),
+            ..RetentionPolicy::default()
+        };
+        assert!(expired(huge_max_age).is_empty());
+        let max_max_age = RetentionPolicy {
+            max_age_days: Some(i64::from(i32::MAX)),
+            ..RetentionPolicy::default()
+        };
+        assert!(expired(max_max_age).is_empty());
+        let max_total_bytes = RetentionPolicy {
+            max_total_bytes: Some(250),
+            ..RetentionPolicy::default()
+        };
+        assert_eq!(expired(max_total_bytes), vec![1, 4]);
+    }
+
+    #[test]
+    fn test_retention_expired_pending() {
+        let episodes = episodes();
+        let downloaded = [(&episodes[2], 100)];
+        let pending = [episodes[0].clone(), episodes[1].clone()];
+        let now = datetime!(2024-03-10 0:00 UTC);
+        let keep_latest = RetentionPolicy {
+            keep_latest: Some(2),
+            ..RetentionPolicy::default()
+        };
+        let mut ids: Vec<_> = keep_latest
+            .expired_pending(&downloaded, &pending, now)
+            .into_iter()
+            .collect();
+        ids.sort_unstable();
+        assert_eq!(ids, vec![1]);
+        let keep_latest = RetentionPolicy {
+            keep_latest: Some(0),
+            ..RetentionPolicy::default()
+        };
+        let mut ids: Vec<_> = keep_latest
+            .expired_pending(&[], &pending, now)
+            .into_iter()
+            .collect();
+        ids.sort_unstable();
+        assert_eq!(ids, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_retention_from_podcast() {
+        let mut podcast = Podcast::default();
+        assert_eq!(RetentionPolicy::from_podcast(&podcast), None);
+        podcast.keep_latest = Some(-1);
+        podcast.max_age_days = Some(-1);
+        podcast.max_total_bytes = Some(-1);
+        assert_eq!(RetentionPolicy::from_podcast(&podcast), None);
+        podcast.keep_latest = Some(0);
+        podcast.max_age_days = Some(30);
+        assert_eq!(
+            RetentionPolicy::from_podcast(&podcast),
+            Some(RetentionPolicy {
+                keep_latest: Some(0),
+                max_age_days: Some(30),
+                max_total_bytes: None,
+            })
+        );
+    }
+}
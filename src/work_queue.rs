@@ -0,0 +1,61 @@
+use deadqueue::unlimited::Queue;
+use futures::future::join_all;
+use std::future::Future;
+
+/// Run `f` over `items` using at most `workers` concurrent tasks fed from a
+/// shared queue, the results are returned in the order of `items`
+pub async fn run_queue<T, R, F, Fut>(items: Vec<T>, workers: usize, f: F) -> Vec<R>
+where
+    F: Fn(T) -> Fut,
+    Fut: Future<Output = R>,
+{
+    let total = items.len();
+    let queue = Queue::new();
+    for item in items.into_iter().enumerate() {
+        queue.push(item);
+    }
+    let workers = (0..workers.clamp(1, total.max(1))).map(|_| async {
+        let mut results = Vec::new();
+        while let Some((idx, item)) = queue.try_pop() {
+            results.push((idx, f(item).await));
+        }
+        results
+    });
+    let mut results: Vec<_> = join_all(workers).await.into_iter().flatten().collect();
+    results.sort_by_key(|(idx, _)| *idx);
+    results.into_iter().map(|(_, r)| r).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+    use tokio::time::sleep;
+
+    use crate::work_queue::run_queue;
+
+    #[tokio::test]
+    async fn test_run_queue() {
+        let running = AtomicUsize::new(0);
+        let max_running = AtomicUsize::new(0);
+        let results = run_queue((0..20).collect(), 3, |x: u64| {
+            let running = &running;
+            let max_running = &max_running;
+            async move {
+                let current = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(current, Ordering::SeqCst);
+                sleep(Duration::from_millis(20 - x)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                x * 2
+            }
+        })
+        .await;
+        assert_eq!(results, (0..20).map(|x| x * 2).collect::<Vec<_>>());
+        assert_eq!(max_running.load(Ordering::SeqCst), 3);
+
+        let results: Vec<u64> = run_queue(Vec::new(), 3, |x: u64| async move { x }).await;
+        assert!(results.is_empty());
+    }
+}